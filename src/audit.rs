@@ -0,0 +1,277 @@
+//! Append-only JSON Lines log of every script the server runs (`--audit-log`).
+//!
+//! Each line records who ran what, where and with what result:
+//!
+//! ```json
+//! {"timestamp":"2025-01-01T12:00:00.000Z","client":{"name":"claude-ai","version":"0.1.0"},
+//!  "tool":"exec","session_id":null,"script":"ls | length","cwd":"/home/me","env":{},
+//!  "inherit_env":true,"policy":"allowed","duration_ms":41,"exit_code":0,...}
+//! ```
+//!
+//! The log is rotated once it reaches its size limit: `audit.jsonl` becomes
+//! `audit.jsonl.1`, `audit.jsonl.1` becomes `audit.jsonl.2` and so on, and the
+//! oldest file beyond the number kept is deleted.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::error;
+
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_KEEP: usize = 5;
+
+/// What environment values, arguments and input are replaced with in redact
+/// mode.
+const REDACTED: &str = "[redacted]";
+
+/// How scripts, and the environment values, arguments and input passed with
+/// them, are written to the audit log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptMode {
+    /// The full script text, environment values, arguments and input
+    #[default]
+    Full,
+    /// Only the SHA-256 of the script, of each environment value and
+    /// argument, and of the input
+    Hash,
+    /// Only the script's length, and no environment values, arguments or
+    /// input
+    Redact,
+}
+
+/// Whether the command policy let a script run.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyDecision {
+    /// No policy is configured.
+    #[default]
+    Unchecked,
+    Allowed,
+    Denied,
+    /// The policy check couldn't be completed, so the script didn't run.
+    Error,
+    /// Module tools are vetted by whoever configured the server.
+    Exempt,
+}
+
+/// Which end of a background job an entry records.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobEvent {
+    Started,
+    Finished,
+}
+
+/// One audit log line. Fields of the run itself stay unset if the script
+/// never ran.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Entry {
+    pub timestamp: String,
+    /// `clientInfo` from the client's initialize request.
+    pub client: Option<Value>,
+    pub tool: String,
+    pub session_id: Option<String>,
+    pub job_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<JobEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    script: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    script_sha256: Option<String>,
+    script_bytes: usize,
+    pub cwd: Option<PathBuf>,
+    pub env: HashMap<String, String>,
+    /// Values bound to script variables through `args`.
+    pub args: Map<String, Value>,
+    /// What the script received as `$in`.
+    pub input: Option<String>,
+    pub inherit_env: bool,
+    pub timeout_seconds: Option<u64>,
+    pub policy: PolicyDecision,
+    pub violations: Option<Value>,
+    pub duration_ms: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub cancelled: bool,
+    pub stdout_bytes: Option<usize>,
+    pub stderr_bytes: Option<usize>,
+    /// Why the call failed, if it did.
+    pub error: Option<String>,
+}
+
+impl Entry {
+    pub fn new(tool: &str, script: &str) -> Self {
+        Self {
+            timestamp: now(),
+            tool: tool.to_string(),
+            script: Some(script.to_string()),
+            script_bytes: script.len(),
+            inherit_env: true,
+            ..Default::default()
+        }
+    }
+
+    /// The entry for the end of the background job that `self` started,
+    /// whose outcome the caller fills in.
+    pub fn job_finished(&self) -> Self {
+        Self {
+            timestamp: now(),
+            event: Some(JobEvent::Finished),
+            ..self.clone()
+        }
+    }
+
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.duration_ms = Some(duration.as_millis() as u64);
+    }
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    scripts: ScriptMode,
+    /// Shared with the logs reopened from this one, so they rotate the file
+    /// under the same lock.
+    file: Arc<Mutex<File>>,
+}
+
+impl AuditLog {
+    /// Opens the log at `path` for appending, creating it if needed.
+    pub fn open(
+        path: &Path,
+        max_bytes: u64,
+        keep: usize,
+        scripts: ScriptMode,
+    ) -> std::io::Result<Self> {
+        let file = open_append(path).map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!("Failed to open audit log {}: {e}", path.display()),
+            )
+        })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            keep,
+            scripts,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Like [`AuditLog::open`], but keeps writing through this log's file if
+    /// `path` is the same, as when the configuration is reloaded.
+    pub fn reopen(
+        &self,
+        path: &Path,
+        max_bytes: u64,
+        keep: usize,
+        scripts: ScriptMode,
+    ) -> std::io::Result<Self> {
+        if path != self.path {
+            return Self::open(path, max_bytes, keep, scripts);
+        }
+        Ok(Self {
+            path: self.path.clone(),
+            max_bytes,
+            keep,
+            scripts,
+            file: self.file.clone(),
+        })
+    }
+
+    /// Appends `entry`, rotating the log first if it would grow past its
+    /// size limit. Failures are logged rather than failing the call.
+    pub fn record(&self, mut entry: Entry) {
+        match self.scripts {
+            ScriptMode::Full => {}
+            ScriptMode::Hash => {
+                let script = entry.script.take().unwrap_or_default();
+                entry.script_sha256 = Some(sha256(&script));
+                for value in entry.env.values_mut() {
+                    *value = sha256(value);
+                }
+                for value in entry.args.values_mut() {
+                    *value = Value::String(sha256(&value.to_string()));
+                }
+                if let Some(input) = &mut entry.input {
+                    *input = sha256(input);
+                }
+            }
+            ScriptMode::Redact => {
+                entry.script = None;
+                for value in entry.env.values_mut() {
+                    *value = REDACTED.to_string();
+                }
+                for value in entry.args.values_mut() {
+                    *value = Value::String(REDACTED.to_string());
+                }
+                if let Some(input) = &mut entry.input {
+                    *input = REDACTED.to_string();
+                }
+            }
+        }
+        let mut line = serde_json::to_string(&entry).unwrap_or_default();
+        line.push('\n');
+
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = self.rotate_if_full(&mut file, line.len() as u64) {
+            error!("Failed to rotate audit log {}: {}", self.path.display(), e);
+        }
+        if let Err(e) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+            error!("Failed to write audit log {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn rotate_if_full(&self, file: &mut File, incoming: u64) -> std::io::Result<()> {
+        let size = file.metadata()?.len();
+        if size == 0 || size + incoming <= self.max_bytes {
+            return Ok(());
+        }
+
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{n}"));
+            PathBuf::from(name)
+        };
+        match self.keep {
+            0 => std::fs::remove_file(&self.path)?,
+            keep => {
+                for n in (1..keep).rev() {
+                    if rotated(n).exists() {
+                        std::fs::rename(rotated(n), rotated(n + 1))?;
+                    }
+                }
+                std::fs::rename(&self.path, rotated(1))?;
+            }
+        }
+        *file = open_append(&self.path)?;
+        Ok(())
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+fn sha256(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text))
+}
+
+/// Opens `path` for appending. The log holds scripts and their environment,
+/// so only the server's user may read it.
+fn open_append(path: &Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
@@ -0,0 +1,233 @@
+//! Bearer-token authentication for the HTTP transport.
+//!
+//! Requests must carry `Authorization: Bearer <token>`. The token is checked
+//! by an [`Authenticator`], which returns the [`Grant`] of tools the caller may
+//! use. Requests without a valid token are rejected with HTTP 401, and calls to
+//! tools outside the grant with HTTP 403, both with a JSON-RPC error body.
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use rmcp::{model::ErrorCode, service::RequestContext, ErrorData as McpError, RoleServer};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Environment variable holding tokens, in addition to `--auth-tokens`.
+pub const TOKENS_ENV_VAR: &str = "MCP_SERVER_NU_AUTH_TOKENS";
+
+/// Largest request body a restricted token may send, since it's buffered to
+/// find the tool being called. Room is left for documents passed as `input`.
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+pub const UNAUTHORIZED: ErrorCode = ErrorCode(-32001);
+pub const FORBIDDEN: ErrorCode = ErrorCode(-32003);
+
+/// The tools a token may use.
+#[derive(Debug, Clone)]
+pub struct Grant {
+    /// `None` allows every tool.
+    tools: Option<HashSet<String>>,
+}
+
+impl Grant {
+    pub fn allows(&self, tool: &str) -> bool {
+        self.tools.as_ref().is_none_or(|tools| tools.contains(tool))
+    }
+
+    /// The grant attached to an HTTP request by [`middleware`], if any.
+    pub fn of(context: &RequestContext<RoleServer>) -> Option<&Grant> {
+        context
+            .extensions
+            .get::<axum::http::request::Parts>()
+            .and_then(|parts| parts.extensions.get::<Grant>())
+    }
+}
+
+/// Decides whether a bearer token is valid and what it may do.
+pub trait Authenticator: Send + Sync {
+    fn authenticate(&self, token: &str) -> Option<Grant>;
+}
+
+/// A fixed set of tokens. Each entry is `token` for access to every tool, or
+/// `token=tool,tool,...` to limit it to the listed tools.
+#[derive(Debug, Default)]
+pub struct StaticTokens {
+    tokens: Vec<(String, Grant)>,
+}
+
+impl StaticTokens {
+    /// Loads the entries of the file at `path` (one per line, `#` starts a
+    /// comment) and of the environment variable [`TOKENS_ENV_VAR`]
+    /// (whitespace-separated). Returns `None` if neither is set, and fails if
+    /// they are but hold no tokens, rather than serving without
+    /// authentication.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Option<Self>> {
+        let env = std::env::var(TOKENS_ENV_VAR).ok();
+        if path.is_none() && env.is_none() {
+            return Ok(None);
+        }
+        let mut tokens = Self::default();
+        if let Some(path) = path {
+            let text = std::fs::read_to_string(path).map_err(|e| {
+                anyhow::anyhow!("Failed to read auth tokens {}: {}", path.display(), e)
+            })?;
+            for line in text.lines() {
+                let line = line.split('#').next().unwrap_or_default();
+                tokens.add_entries(line)?;
+            }
+        }
+        if let Some(value) = env {
+            tokens.add_entries(&value)?;
+        }
+        if tokens.tokens.is_empty() {
+            let source = match path {
+                Some(path) => path.display().to_string(),
+                None => TOKENS_ENV_VAR.to_string(),
+            };
+            anyhow::bail!("No auth tokens found in {source}; refusing to serve without them");
+        }
+        Ok(Some(tokens))
+    }
+
+    fn add_entries(&mut self, entries: &str) -> anyhow::Result<()> {
+        for entry in entries.split_whitespace() {
+            let (token, tools) = match entry.split_once('=') {
+                Some((token, tools)) => (
+                    token,
+                    Some(
+                        tools
+                            .split(',')
+                            .filter(|tool| !tool.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                    ),
+                ),
+                None => (entry, None),
+            };
+            if token.is_empty() {
+                anyhow::bail!("Auth token entry '{entry}' has an empty token");
+            }
+            self.tokens.push((token.to_string(), Grant { tools }));
+        }
+        Ok(())
+    }
+}
+
+impl Authenticator for StaticTokens {
+    fn authenticate(&self, token: &str) -> Option<Grant> {
+        // Compare against every token so the time taken doesn't reveal which
+        // one, or how much of it, matched.
+        let mut grant = None;
+        for (candidate, candidate_grant) in &self.tokens {
+            if constant_time_eq(candidate.as_bytes(), token.as_bytes()) {
+                grant = Some(candidate_grant.clone());
+            }
+        }
+        grant
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Axum middleware that authenticates every request to the MCP endpoint.
+pub async fn middleware(
+    State(authenticator): State<Arc<dyn Authenticator>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    let Some(grant) = token.and_then(|token| authenticator.authenticate(token)) else {
+        warn!("Rejected request without a valid bearer token");
+        let mut response = rpc_error(
+            StatusCode::UNAUTHORIZED,
+            Value::Null,
+            UNAUTHORIZED,
+            "Missing or invalid bearer token",
+        );
+        response
+            .headers_mut()
+            .insert(header::WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+        return response;
+    };
+
+    // Only restricted tokens need to look inside the body.
+    if grant.tools.is_some() {
+        let (parts, body) = request.into_parts();
+        let bytes = match to_bytes(body, MAX_BODY_BYTES).await {
+            Ok(bytes) => bytes,
+            Err(e) if is_length_limit(&e) => {
+                warn!("Rejected request body larger than {} bytes", MAX_BODY_BYTES);
+                return rpc_error(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    Value::Null,
+                    ErrorCode::INVALID_REQUEST,
+                    &format!("Request body is larger than {MAX_BODY_BYTES} bytes"),
+                );
+            }
+            Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        };
+        if let Some((id, tool)) = forbidden_call(&grant, &bytes) {
+            warn!("Rejected call to tool '{}' not granted to token", tool);
+            return rpc_error(
+                StatusCode::FORBIDDEN,
+                id,
+                FORBIDDEN,
+                &format!("Token is not permitted to call tool '{tool}'"),
+            );
+        }
+        request = Request::from_parts(parts, Body::from(bytes));
+    }
+
+    request.extensions_mut().insert(grant);
+    next.run(request).await
+}
+
+/// Whether reading a body failed because it ran past the limit.
+fn is_length_limit(error: &axum::Error) -> bool {
+    let mut source = std::error::Error::source(error);
+    while let Some(error) = source {
+        if error.is::<http_body_util::LengthLimitError>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// Finds a `tools/call` in a JSON-RPC message (or batch) for a tool the grant
+/// doesn't include, returning its request id and tool name.
+fn forbidden_call(grant: &Grant, body: &[u8]) -> Option<(Value, String)> {
+    let message: Value = serde_json::from_slice(body).ok()?;
+    let messages = match message {
+        Value::Array(messages) => messages,
+        message => vec![message],
+    };
+    messages.into_iter().find_map(|message| {
+        if message["method"] != "tools/call" {
+            return None;
+        }
+        let tool = message["params"]["name"].as_str().unwrap_or_default();
+        (!grant.allows(tool)).then(|| (message["id"].clone(), tool.to_string()))
+    })
+}
+
+pub fn rpc_error(status: StatusCode, id: Value, code: ErrorCode, message: &str) -> Response {
+    let error = McpError::new(code, message.to_string(), None);
+    let body = json!({ "jsonrpc": "2.0", "id": id, "error": error });
+    (status, Json(body)).into_response()
+}
@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+
+/// Hints for the POSIX habits the `exec` description warns about, keyed on the
+/// source text a diagnostic points at.
+const POSIX_HINTS: &[(&str, &str)] = &[
+    (
+        "&&",
+        "Nushell has no '&&'. Use ';' to run commands in sequence, or 'and' for boolean logic.",
+    ),
+    (
+        "||",
+        "Nushell has no '||'. Use 'try { ... } catch { ... }' to handle failure, or 'or' for boolean logic.",
+    ),
+    (
+        "\\",
+        "Nushell has no trailing-backslash line continuation. Wrap multi-line pipelines in parentheses instead.",
+    ),
+    (
+        "$(",
+        "Use '(command)' for subexpressions instead of POSIX '$(command)'.",
+    ),
+];
+
+/// A line as reported by `nu --ide-check`.
+#[derive(Debug, Deserialize)]
+struct IdeMessage {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    severity: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    span: Option<ByteSpan>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct ByteSpan {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A 1-based line and column (in characters) within the script.
+#[derive(Debug, Serialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    pub severity: String,
+    pub message: String,
+    pub span: ByteSpan,
+    pub start: Position,
+    pub end: Position,
+    /// The part of the script the diagnostic points at.
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub help: Option<String>,
+}
+
+/// Turns the JSON lines printed by `nu --ide-check` for `script` into
+/// diagnostics with line/column positions.
+pub fn parse_ide_check(script: &str, stdout: &str) -> Vec<Diagnostic> {
+    stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<IdeMessage>(line).ok())
+        .filter(|message| message.kind == "diagnostic")
+        .map(|message| {
+            let span = message.span.unwrap_or(ByteSpan { start: 0, end: 0 });
+            let start = floor_char_boundary(script, span.start);
+            let end = floor_char_boundary(script, span.end.max(start));
+            let text = script[start..end].to_string();
+            let help = POSIX_HINTS
+                .iter()
+                .find(|(snippet, _)| text.trim() == *snippet)
+                .map(|(_, help)| help.to_string());
+
+            Diagnostic {
+                severity: message.severity.unwrap_or_else(|| "Error".to_string()),
+                message: message.message.unwrap_or_default(),
+                span: ByteSpan { start, end },
+                start: position(script, start),
+                end: position(script, end),
+                text,
+                help,
+            }
+        })
+        .collect()
+}
+
+fn floor_char_boundary(s: &str, mut index: usize) -> usize {
+    index = index.min(s.len());
+    while !s.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Maps a byte offset in `script` to a line and column.
+pub fn position(script: &str, offset: usize) -> Position {
+    let before = &script[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    Position {
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
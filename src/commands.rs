@@ -0,0 +1,115 @@
+//! Command discovery backed by nushell's `scope commands`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::script::raw_string;
+
+/// Lists every command in scope as JSON. Optional cell paths and the
+/// `search_terms` flattening keep this working across nushell versions.
+pub const LIST_COMMANDS_SCRIPT: &str = r#"scope commands | each {|c| {
+  name: $c.name
+  category: $c.category
+  type: $c.type
+  description: ($c.description? | default $c.usage? | default '')
+  search_terms: ([($c.search_terms? | default '')] | flatten | str join ' ')
+} } | to json --raw"#;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CommandSummary {
+    pub name: String,
+    pub category: String,
+    #[serde(rename = "type")]
+    pub command_type: String,
+    pub description: String,
+    #[serde(skip_serializing)]
+    pub search_terms: String,
+}
+
+/// Keeps the commands matching every given filter. Matching is
+/// case-insensitive; `search` looks at the name, description and search terms.
+pub fn filter_commands(
+    commands: Vec<CommandSummary>,
+    category: Option<&str>,
+    command_type: Option<&str>,
+    search: Option<&str>,
+) -> Vec<CommandSummary> {
+    let search = search.map(str::to_lowercase);
+    commands
+        .into_iter()
+        .filter(|c| category.is_none_or(|category| c.category.eq_ignore_ascii_case(category)))
+        .filter(|c| {
+            command_type
+                .is_none_or(|command_type| c.command_type.eq_ignore_ascii_case(command_type))
+        })
+        .filter(|c| {
+            search.as_deref().is_none_or(|search| {
+                [&c.name, &c.description, &c.search_terms]
+                    .iter()
+                    .any(|field| field.to_lowercase().contains(search))
+            })
+        })
+        .collect()
+}
+
+/// Describes a single command as JSON, or `null` if there is no such command.
+pub fn describe_command_script(name: &str) -> String {
+    format!(
+        r#"scope commands | where name == {} | each {{|c| {{
+  name: $c.name
+  category: $c.category
+  type: $c.type
+  description: ($c.description? | default $c.usage? | default '')
+  extra_description: ($c.extra_description? | default $c.extra_usage? | default '')
+  search_terms: ([($c.search_terms? | default '')] | flatten | str join ' ')
+  signatures: $c.signatures
+  examples: ($c.examples | each {{|e| {{description: $e.description, example: $e.example}} }})
+}} }} | get 0? | to json --raw"#,
+        raw_string(name)
+    )
+}
+
+/// Reshapes the `signatures` record of `scope commands` (keyed by input type)
+/// into a list of signatures with parameters split by kind.
+pub fn reshape_signatures(command: &mut Value) {
+    let Some(Value::Object(signatures)) = command.get("signatures").cloned() else {
+        return;
+    };
+
+    let reshaped: Vec<Value> = signatures
+        .into_iter()
+        .map(|(input_type, params)| {
+            let params = params.as_array().cloned().unwrap_or_default();
+            let output_type = params
+                .iter()
+                .find(|p| p["parameter_type"] == "output")
+                .map(|p| p["syntax_shape"].clone())
+                .unwrap_or(Value::Null);
+            let of_kind = |kinds: &[&str]| -> Vec<Value> {
+                params
+                    .iter()
+                    .filter(|p| kinds.iter().any(|kind| p["parameter_type"] == *kind))
+                    .map(|p| {
+                        serde_json::json!({
+                            "name": p["parameter_name"],
+                            "kind": p["parameter_type"],
+                            "shape": p["syntax_shape"],
+                            "optional": p["is_optional"],
+                            "short_flag": p["short_flag"],
+                            "description": p["description"],
+                        })
+                    })
+                    .collect()
+            };
+
+            serde_json::json!({
+                "input_type": input_type,
+                "output_type": output_type,
+                "positional": of_kind(&["positional", "rest"]),
+                "flags": of_kind(&["named", "switch"]),
+            })
+        })
+        .collect();
+
+    command["signatures"] = Value::Array(reshaped);
+}
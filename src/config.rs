@@ -0,0 +1,387 @@
+//! Server settings from a TOML file (`--config`), with named profiles.
+//!
+//! Keys are the long CLI options with `-` replaced by `_`, plus a few that
+//! only exist in the file. A profile overrides the top-level values, and CLI
+//! flags override both:
+//!
+//! ```toml
+//! nu_bin = "/usr/local/bin/nu"
+//! default_timeout_seconds = 30
+//! max_timeout_seconds = 300
+//! max_output_bytes = 65536
+//! policy = "policy.toml"
+//! env = { PAGER = "cat" }
+//! log = "info"
+//!
+//! [profiles.ci]
+//! read_only = true
+//! allow_external = ["git"]
+//! limit_cpu_seconds = 60
+//! ```
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::audit::{self, ScriptMode};
+use crate::limits::ResourceLimits;
+use crate::output;
+
+pub const DEFAULT_TIMEOUT_SECONDS: u64 = 30;
+pub const DEFAULT_BIND: &str = "127.0.0.1:8000";
+const DEFAULT_LOG: &str = "error";
+const DEFAULT_NU_BIN: &str = "nu";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// Serve a single client over stdin/stdout
+    Stdio,
+    /// Serve any number of clients over MCP streamable HTTP at `/mcp`
+    Http,
+}
+
+/// Settings from the config file or the command line. Unset values fall back
+/// to the next source, and finally to the defaults of the accessors below.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// The nu binary, looked up on PATH unless it's a path.
+    pub nu_bin: Option<PathBuf>,
+    pub nu_config: Option<String>,
+    pub nu_env_config: Option<String>,
+    pub tools_module: Option<PathBuf>,
+    pub policy: Option<PathBuf>,
+    pub read_only: Option<bool>,
+    pub allow_external: Option<Vec<String>>,
+    pub sandbox: Option<bool>,
+    pub sandbox_read: Option<Vec<PathBuf>>,
+    pub sandbox_write: Option<Vec<PathBuf>>,
+    pub sandbox_no_network: Option<bool>,
+    pub allowed_roots: Option<Vec<PathBuf>>,
+    /// Timeout of `exec` calls that don't set one.
+    pub default_timeout_seconds: Option<u64>,
+    /// Longest timeout an `exec` call may ask for.
+    pub max_timeout_seconds: Option<u64>,
+    pub max_output_bytes: Option<usize>,
+    pub limit_memory_mb: Option<u64>,
+    pub limit_cpu_seconds: Option<u64>,
+    pub limit_processes: Option<u64>,
+    pub limit_open_files: Option<u64>,
+    pub limit_file_size_mb: Option<u64>,
+    /// Environment variables set for every script.
+    pub env: Option<HashMap<String, String>>,
+    pub transport: Option<Transport>,
+    pub bind: Option<SocketAddr>,
+    pub auth_tokens: Option<PathBuf>,
+    pub allowed_hosts: Option<Vec<String>>,
+    pub allowed_origins: Option<Vec<String>>,
+    pub listen_unix: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub audit_log_max_bytes: Option<u64>,
+    pub audit_log_keep: Option<usize>,
+    pub audit_scripts: Option<ScriptMode>,
+    pub watch: Option<bool>,
+    /// Log filter, e.g. `info` or `mcp_server_nu=debug`. `RUST_LOG` takes
+    /// precedence.
+    pub log: Option<String>,
+}
+
+macro_rules! overlay_fields {
+    ($base:expr, $top:expr, $($field:ident),* $(,)?) => {
+        $(
+            if $top.$field.is_some() {
+                $base.$field = $top.$field;
+            }
+        )*
+    };
+}
+
+impl Settings {
+    /// Loads the top-level settings of the config file at `path`, overridden
+    /// by those of `profile` if given. Relative paths in the file are resolved
+    /// against its directory.
+    pub fn load(path: &Path, profile: Option<&str>) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read config {}: {}", path.display(), e))?;
+        let invalid =
+            |e: toml::de::Error| anyhow::anyhow!("Invalid config {}: {}", path.display(), e);
+
+        let mut table: toml::Table = toml::from_str(&text).map_err(invalid)?;
+        let profiles = match table.remove("profiles") {
+            Some(toml::Value::Table(profiles)) => profiles,
+            Some(_) => anyhow::bail!(
+                "Invalid config {}: profiles must be a table",
+                path.display()
+            ),
+            None => toml::Table::new(),
+        };
+        let mut settings = Settings::deserialize(table).map_err(invalid)?;
+
+        if let Some(name) = profile {
+            let Some(overrides) = profiles.get(name) else {
+                let mut available: Vec<_> = profiles.keys().map(String::as_str).collect();
+                available.sort();
+                anyhow::bail!(
+                    "Unknown profile '{}' in {} (available: {})",
+                    name,
+                    path.display(),
+                    match available.is_empty() {
+                        true => "none".to_string(),
+                        false => available.join(", "),
+                    }
+                );
+            };
+            let overrides = Settings::deserialize(overrides.clone()).map_err(|e| {
+                anyhow::anyhow!("Invalid profile '{}' in {}: {}", name, path.display(), e)
+            })?;
+            settings.overlay(overrides);
+        }
+
+        if let Some(dir) = path.parent() {
+            settings.resolve_paths(dir);
+        }
+        Ok(settings)
+    }
+
+    /// Replaces every value that is set in `top`.
+    pub fn overlay(&mut self, top: Settings) {
+        overlay_fields!(
+            self,
+            top,
+            nu_bin,
+            nu_config,
+            nu_env_config,
+            tools_module,
+            policy,
+            read_only,
+            allow_external,
+            sandbox,
+            sandbox_read,
+            sandbox_write,
+            sandbox_no_network,
+            allowed_roots,
+            default_timeout_seconds,
+            max_timeout_seconds,
+            max_output_bytes,
+            limit_memory_mb,
+            limit_cpu_seconds,
+            limit_processes,
+            limit_open_files,
+            limit_file_size_mb,
+            env,
+            transport,
+            bind,
+            auth_tokens,
+            allowed_hosts,
+            allowed_origins,
+            listen_unix,
+            audit_log,
+            audit_log_max_bytes,
+            audit_log_keep,
+            audit_scripts,
+            watch,
+            log,
+        );
+    }
+
+    /// Checks that the settings make sense together.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let enabled = |flag: Option<bool>| flag.unwrap_or(false);
+        if !enabled(self.sandbox) {
+            for (name, set) in [
+                ("sandbox_read", self.sandbox_read.is_some()),
+                ("sandbox_write", self.sandbox_write.is_some()),
+                ("sandbox_no_network", enabled(self.sandbox_no_network)),
+            ] {
+                if set {
+                    anyhow::bail!("{name} requires sandbox to be enabled");
+                }
+            }
+        }
+        if self.allow_external.is_some() && !enabled(self.read_only) {
+            anyhow::bail!("allow_external requires read_only to be enabled");
+        }
+        if self.listen_unix.is_some() {
+            if !cfg!(unix) {
+                anyhow::bail!("listen_unix is only supported on Unix");
+            }
+            if self.transport.is_some() {
+                anyhow::bail!("listen_unix can't be combined with transport");
+            }
+        }
+        if self.transport != Some(Transport::Http) {
+            for (name, set) in [
+                ("auth_tokens", self.auth_tokens.is_some()),
+                ("allowed_hosts", self.allowed_hosts.is_some()),
+                ("allowed_origins", self.allowed_origins.is_some()),
+            ] {
+                if set {
+                    anyhow::bail!("{name} requires transport to be http");
+                }
+            }
+        }
+        if self.default_timeout_seconds == Some(0) || self.max_timeout_seconds == Some(0) {
+            anyhow::bail!("Timeouts must be at least one second");
+        }
+        if self.default_timeout() > self.max_timeout() {
+            anyhow::bail!(
+                "default_timeout_seconds ({}) is greater than max_timeout_seconds ({})",
+                self.default_timeout(),
+                self.max_timeout()
+            );
+        }
+        if self.audit_log.is_none() {
+            for (name, set) in [
+                ("audit_log_max_bytes", self.audit_log_max_bytes.is_some()),
+                ("audit_log_keep", self.audit_log_keep.is_some()),
+                ("audit_scripts", self.audit_scripts.is_some()),
+            ] {
+                if set {
+                    anyhow::bail!("{name} requires audit_log to be set");
+                }
+            }
+        }
+        if self.audit_log_max_bytes == Some(0) {
+            anyhow::bail!("audit_log_max_bytes must be greater than zero");
+        }
+        if self.max_output_bytes == Some(0) {
+            anyhow::bail!("max_output_bytes must be greater than zero");
+        }
+        if let Some(name) = self.env.iter().flatten().find_map(|(name, value)| {
+            (name.is_empty() || name.contains(['=', '\0']) || value.contains('\0')).then_some(name)
+        }) {
+            anyhow::bail!("Invalid environment variable in env: {name:?}");
+        }
+        if let Some(log) = &self.log {
+            tracing_subscriber::EnvFilter::try_new(log)
+                .map_err(|e| anyhow::anyhow!("Invalid log filter '{}': {}", log, e))?;
+        }
+        Ok(())
+    }
+
+    /// The files, other than the config file, that `watch` reloads on.
+    pub fn watched_files(&self) -> Vec<PathBuf> {
+        [&self.tools_module, &self.policy]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .chain(
+                [&self.nu_config, &self.nu_env_config]
+                    .into_iter()
+                    .flatten()
+                    .map(PathBuf::from),
+            )
+            .collect()
+    }
+
+    /// Which settings a reload to `new` changes that only apply at startup.
+    pub fn restart_required(&self, new: &Settings) -> [(&'static str, bool); 8] {
+        [
+            ("transport", self.transport != new.transport),
+            ("bind", self.bind != new.bind),
+            ("auth_tokens", self.auth_tokens != new.auth_tokens),
+            ("allowed_hosts", self.allowed_hosts != new.allowed_hosts),
+            (
+                "allowed_origins",
+                self.allowed_origins != new.allowed_origins,
+            ),
+            ("listen_unix", self.listen_unix != new.listen_unix),
+            ("watch", self.watch != new.watch),
+            ("log", self.log != new.log),
+        ]
+    }
+
+    /// Defaults to 30 seconds, or the maximum if that's lower.
+    pub fn default_timeout(&self) -> u64 {
+        self.default_timeout_seconds
+            .unwrap_or(DEFAULT_TIMEOUT_SECONDS.min(self.max_timeout()))
+    }
+
+    /// Defaults to no maximum.
+    pub fn max_timeout(&self) -> u64 {
+        self.max_timeout_seconds.unwrap_or(u64::MAX)
+    }
+
+    pub fn max_output_bytes(&self) -> usize {
+        self.max_output_bytes.unwrap_or(output::DEFAULT_MAX_BYTES)
+    }
+
+    pub fn audit_log_max_bytes(&self) -> u64 {
+        self.audit_log_max_bytes.unwrap_or(audit::DEFAULT_MAX_BYTES)
+    }
+
+    pub fn audit_log_keep(&self) -> usize {
+        self.audit_log_keep.unwrap_or(audit::DEFAULT_KEEP)
+    }
+
+    pub fn limits(&self) -> ResourceLimits {
+        ResourceLimits {
+            memory_mb: self.limit_memory_mb,
+            cpu_seconds: self.limit_cpu_seconds,
+            processes: self.limit_processes,
+            open_files: self.limit_open_files,
+            file_size_mb: self.limit_file_size_mb,
+        }
+    }
+
+    pub fn nu_bin(&self) -> PathBuf {
+        self.nu_bin
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_NU_BIN))
+    }
+
+    pub fn bind(&self) -> SocketAddr {
+        self.bind.unwrap_or_else(|| DEFAULT_BIND.parse().unwrap())
+    }
+
+    pub fn log(&self) -> &str {
+        self.log.as_deref().unwrap_or(DEFAULT_LOG)
+    }
+
+    fn resolve_paths(&mut self, dir: &Path) {
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() {
+                *path = dir.join(&*path);
+            }
+        };
+        // A bare binary name is looked up on PATH.
+        if let Some(nu_bin) = &mut self.nu_bin {
+            if nu_bin.components().count() > 1 {
+                resolve(nu_bin);
+            }
+        }
+        for path in [&mut self.nu_config, &mut self.nu_env_config]
+            .into_iter()
+            .flatten()
+        {
+            if Path::new(path).is_relative() {
+                *path = dir.join(&*path).to_string_lossy().into_owned();
+            }
+        }
+        for path in [
+            &mut self.tools_module,
+            &mut self.policy,
+            &mut self.auth_tokens,
+            &mut self.listen_unix,
+            &mut self.audit_log,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            resolve(path);
+        }
+        for paths in [
+            &mut self.sandbox_read,
+            &mut self.sandbox_write,
+            &mut self.allowed_roots,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            paths.iter_mut().for_each(resolve);
+        }
+    }
+}
@@ -0,0 +1,395 @@
+//! Parses the errors nushell renders to stderr (miette's graphical report
+//! format) back into structured data.
+//!
+//! A rendered error looks like:
+//!
+//! ```text
+//! Error: nu::parser::parse_mismatch
+//!
+//!   × Parse mismatch during operation.
+//!    ╭─[source:1:1]
+//!  1 │ ls | where size >
+//!    ·                 ┬
+//!    ·                 ╰── expected operand
+//!    ╰────
+//!   help: ...
+//! ```
+
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Debug, Default, Serialize)]
+pub struct NuError {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    pub message: String,
+    pub labels: Vec<Label>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub help: Option<String>,
+}
+
+/// A labelled span. `line` and the columns are 1-based and refer to `source`:
+/// `script` for the script passed to `exec`, or the path of another file such
+/// as a config file or module.
+#[derive(Debug, Serialize)]
+pub struct Label {
+    pub source: String,
+    pub line: usize,
+    pub column: usize,
+    pub end_column: usize,
+    pub text: String,
+}
+
+/// Returns every nushell error found in `stderr`.
+pub fn parse_errors(stderr: &str) -> Vec<NuError> {
+    let stderr = strip_ansi(stderr);
+    let mut errors = Vec::new();
+    let mut current: Option<Parser> = None;
+
+    for line in stderr.lines() {
+        if let Some(rest) = line.strip_prefix("Error: ") {
+            errors.extend(current.take().map(Parser::finish));
+            let mut parser = Parser::default();
+            let rest = rest.trim();
+            if rest.contains("::") && !rest.contains(' ') {
+                parser.error.code = Some(rest.to_string());
+            } else {
+                parser.error.message = rest.trim_start_matches("× ").to_string();
+            }
+            current = Some(parser);
+        } else if let Some(parser) = &mut current {
+            parser.line(line);
+        }
+    }
+    errors.extend(current.map(Parser::finish));
+    errors
+}
+
+/// Renames the source of labels that point into the user's script to
+/// `script`. `script_file` is the file the script was sourced from, if it was
+/// run through a wrapper; otherwise it was passed to `nu -c` directly and any
+/// label that isn't on a file path belongs to it.
+pub fn attribute_to_script(errors: &mut [NuError], script_file: Option<&Path>) {
+    for label in errors.iter_mut().flat_map(|error| error.labels.iter_mut()) {
+        let source = Path::new(&label.source);
+        let in_script = match script_file {
+            Some(script_file) => source == script_file,
+            None => !source.is_absolute(),
+        };
+        if in_script {
+            label.source = "script".to_string();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Parser {
+    error: NuError,
+    source: String,
+    /// Snippet line number and the char index where its code starts.
+    snippet: Option<(usize, usize)>,
+    /// Underlined segments of the current snippet line: (start, end, marker)
+    /// as char indexes.
+    segments: Vec<(usize, usize, usize)>,
+    in_help: bool,
+}
+
+impl Parser {
+    fn line(&mut self, line: &str) {
+        let trimmed = line.trim();
+        let chars: Vec<char> = line.chars().collect();
+
+        if let Some(message) = trimmed.strip_prefix("× ") {
+            self.in_help = false;
+            if self.error.message.is_empty() {
+                self.error.message = message.to_string();
+            }
+        } else if let Some(help) = trimmed.strip_prefix("help: ") {
+            self.in_help = true;
+            self.error.help = Some(help.to_string());
+        } else if let Some(header) = trimmed.strip_prefix("╭─[") {
+            self.in_help = false;
+            let header = header.trim_end_matches(']');
+            // `name:line:column`; the name itself may contain colons.
+            let mut parts = header.rsplitn(3, ':');
+            let _column = parts.next();
+            let _line = parts.next();
+            self.source = parts.next().unwrap_or(header).to_string();
+        } else if let Some(gutter) = chars.iter().position(|&c| c == '│' || c == '·') {
+            // Label lines may contain '│' connectors too, so the first gutter
+            // character decides the kind of line.
+            if chars[gutter] == '·' {
+                self.label_line(&chars, gutter + 2);
+            } else if let Ok(number) = chars[..gutter].iter().collect::<String>().trim().parse() {
+                self.snippet = Some((number, gutter + 2));
+                self.segments.clear();
+            }
+        } else if self.in_help && !trimmed.is_empty() {
+            if let Some(help) = &mut self.error.help {
+                help.push('\n');
+                help.push_str(trimmed);
+            }
+        } else if trimmed.is_empty() {
+            self.in_help = false;
+        }
+    }
+
+    /// Handles the lines under a snippet line: first the underlines, then one
+    /// line per label text.
+    fn label_line(&mut self, chars: &[char], code_start: usize) {
+        let Some((line, _)) = self.snippet else {
+            return;
+        };
+        let body = chars.get(code_start..).unwrap_or_default();
+
+        if self.segments.is_empty() {
+            let mut i = 0;
+            while i < body.len() {
+                if matches!(body[i], '─' | '┬') {
+                    let start = i;
+                    let mut marker = i;
+                    while i < body.len() && matches!(body[i], '─' | '┬') {
+                        if body[i] == '┬' {
+                            marker = i;
+                        }
+                        i += 1;
+                    }
+                    self.segments.push((start, i - 1, marker));
+                } else {
+                    i += 1;
+                }
+            }
+            return;
+        }
+
+        if let Some(marker) = body.iter().position(|&c| c == '╰') {
+            let text: String = body[marker..]
+                .iter()
+                .skip_while(|&&c| matches!(c, '╰' | '─'))
+                .collect();
+            let (start, end) = self
+                .segments
+                .iter()
+                .find(|(_, _, m)| *m == marker)
+                .map(|(start, end, _)| (*start, *end))
+                .unwrap_or((marker, marker));
+            self.error.labels.push(Label {
+                source: self.source.clone(),
+                line,
+                column: start + 1,
+                end_column: end + 1,
+                text: text.trim().to_string(),
+            });
+        }
+    }
+
+    fn finish(self) -> NuError {
+        self.error
+    }
+}
+
+/// Removes ANSI CSI and OSC escape sequences.
+pub fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    /// (name, stderr, expected errors)
+    fn cases() -> Vec<(&'static str, String, Value)> {
+        vec![
+            (
+                "parse error",
+                [
+                    "Error: nu::parser::parse_mismatch",
+                    "",
+                    "  × Parse mismatch during operation.",
+                    "   ╭─[source:1:17]",
+                    " 1 │ ls | where size >",
+                    "   ·                 ┬",
+                    "   ·                 ╰── expected operand",
+                    "   ╰────",
+                ]
+                .join("\n"),
+                json!([{
+                    "code": "nu::parser::parse_mismatch",
+                    "message": "Parse mismatch during operation.",
+                    "labels": [{
+                        "source": "source",
+                        "line": 1,
+                        "column": 17,
+                        "end_column": 17,
+                        "text": "expected operand",
+                    }],
+                }]),
+            ),
+            (
+                "ansi colors and hyperlinked code",
+                [
+                    "Error: \x1b]8;;https://docs.rs/nu-protocol\x1b\\\x1b[31mnu::shell::column_not_found\x1b[0m\x1b]8;;\x1b\\",
+                    "",
+                    "  \x1b[31m×\x1b[0m Cannot find column 'foo'",
+                    "   ╭─[\x1b[36;1;4msource\x1b[0m:1:10]",
+                    " \x1b[2m1\x1b[0m │ ls | get foo",
+                    "   · \x1b[35;1m         ─┬─\x1b[0m",
+                    "   ·           \x1b[35;1m╰── \x1b[35;1mcannot find column 'foo'\x1b[0m\x1b[0m",
+                    "   ╰────",
+                    "\x1b[36m  help: \x1b[0mTry one of: name, type",
+                ]
+                .join("\n"),
+                json!([{
+                    "code": "nu::shell::column_not_found",
+                    "message": "Cannot find column 'foo'",
+                    "labels": [{
+                        "source": "source",
+                        "line": 1,
+                        "column": 10,
+                        "end_column": 12,
+                        "text": "cannot find column 'foo'",
+                    }],
+                    "help": "Try one of: name, type",
+                }]),
+            ),
+            (
+                "two labels on one line",
+                [
+                    "Error: nu::shell::type_mismatch",
+                    "",
+                    "  × Type mismatch during operation.",
+                    "   ╭─[/home/me/my:config.nu:2:1]",
+                    " 1 │ let x = 1",
+                    " 2 │ $x + 'a'",
+                    "   · ─┬ ┬ ─┬─",
+                    "   ·  │ │  ╰── string",
+                    "   ·  │ ╰── type mismatch for operator",
+                    "   ·  ╰── int",
+                    "   ╰────",
+                ]
+                .join("\n"),
+                json!([{
+                    "code": "nu::shell::type_mismatch",
+                    "message": "Type mismatch during operation.",
+                    "labels": [
+                        {
+                            "source": "/home/me/my:config.nu",
+                            "line": 2,
+                            "column": 6,
+                            "end_column": 8,
+                            "text": "string",
+                        },
+                        {
+                            "source": "/home/me/my:config.nu",
+                            "line": 2,
+                            "column": 4,
+                            "end_column": 4,
+                            "text": "type mismatch for operator",
+                        },
+                        {
+                            "source": "/home/me/my:config.nu",
+                            "line": 2,
+                            "column": 1,
+                            "end_column": 2,
+                            "text": "int",
+                        },
+                    ],
+                }]),
+            ),
+            (
+                "message without a code, multi-line help, then a second error",
+                [
+                    "Error:   × Division by zero.",
+                    "  help: Check the divisor",
+                    "        before dividing.",
+                    "",
+                    "Error: nu::shell::external_command",
+                    "",
+                    "  × External command failed",
+                ]
+                .join("\n"),
+                json!([
+                    {
+                        "message": "Division by zero.",
+                        "labels": [],
+                        "help": "Check the divisor\nbefore dividing.",
+                    },
+                    {
+                        "code": "nu::shell::external_command",
+                        "message": "External command failed",
+                        "labels": [],
+                    },
+                ]),
+            ),
+            ("no errors", "plain output\n".to_string(), json!([])),
+        ]
+    }
+
+    #[test]
+    fn parses_rendered_errors() {
+        for (name, stderr, expected) in cases() {
+            assert_eq!(json!(parse_errors(&stderr)), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn attributes_labels_to_the_script() {
+        let stderr = [
+            "Error: nu::shell::error",
+            "   ╭─[/tmp/s/script.nu:1:1]",
+            " 1 │ x",
+            "   · ┬",
+            "   · ╰── here",
+            "   ╰────",
+            "   ╭─[/etc/config.nu:1:1]",
+            " 1 │ y",
+            "   · ┬",
+            "   · ╰── there",
+            "   ╰────",
+        ]
+        .join("\n");
+        let mut errors = parse_errors(&stderr);
+        attribute_to_script(&mut errors, Some(Path::new("/tmp/s/script.nu")));
+        let sources: Vec<_> = errors[0].labels.iter().map(|l| &l.source[..]).collect();
+        assert_eq!(sources, ["script", "/etc/config.nu"]);
+    }
+
+    #[test]
+    fn strips_csi_and_osc_sequences() {
+        assert_eq!(
+            strip_ansi("\x1b[1;31mred\x1b[0m \x1b]8;;file:///x\x07link\x1b]8;;\x1b\\"),
+            "red link"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio_util::sync::CancellationToken;
+
+/// Longest line handed to `on_line`; longer lines arrive in pieces, so
+/// output without newlines can't grow a line without bound.
+const MAX_LINE_BYTES: usize = 1024 * 1024;
+
+/// How long output is still read after a kill. A descendant that left the
+/// process group survives the kill and can hold the pipes open for as long
+/// as it runs.
+const KILL_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// How a nu process finished.
+#[derive(Debug, Default)]
+pub struct Completion {
+    pub exit_code: i32,
+    /// The signal that terminated the process, if any (Unix only).
+    pub signal: Option<i32>,
+    /// The process was killed because `cancel` fired.
+    pub cancelled: bool,
+    /// The process was killed because it ran past its timeout.
+    pub timed_out: bool,
+    pub elapsed: Duration,
+}
+
+/// Runs `cmd` to completion, reading stdout and stderr line by line. Each line
+/// is handed to `on_line` as soon as it arrives; collecting it is up to the
+/// caller.
+///
+/// The process is started in its own process group. If `cancel` fires or
+/// `timeout` elapses, the whole group is killed and output arriving within
+/// [`KILL_GRACE`] is still collected before returning.
+pub async fn run<F>(
+    cmd: Command,
+    timeout: Option<Duration>,
+    cancel: &CancellationToken,
+    on_line: F,
+) -> std::io::Result<Completion>
+where
+    F: FnMut(Stream, &str),
+{
+    supervise(spawn(cmd)?, timeout, cancel, on_line).await
+}
+
+/// Starts `cmd` in its own process group with stdout and stderr piped, ready
+/// for [`supervise`].
+pub fn spawn(mut cmd: Command) -> std::io::Result<Child> {
+    #[cfg(unix)]
+    cmd.process_group(0);
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()
+}
+
+/// Does the work of [`run`] for a child started with [`spawn`].
+pub async fn supervise<F>(
+    mut child: Child,
+    timeout: Option<Duration>,
+    cancel: &CancellationToken,
+    mut on_line: F,
+) -> std::io::Result<Completion>
+where
+    F: FnMut(Stream, &str),
+{
+    let started = Instant::now();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+
+    let mut completion = Completion::default();
+    let mut stdout_open = true;
+    let mut stderr_open = true;
+    let mut stdout_line = Vec::new();
+    let mut stderr_line = Vec::new();
+    let deadline = async {
+        match timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(deadline);
+    let grace = tokio::time::sleep(KILL_GRACE);
+    tokio::pin!(grace);
+
+    while stdout_open || stderr_open {
+        let killed = completion.cancelled || completion.timed_out;
+        tokio::select! {
+            _ = cancel.cancelled(), if !killed => {
+                kill_process_group(&mut child);
+                completion.cancelled = true;
+                grace.as_mut().reset(tokio::time::Instant::now() + KILL_GRACE);
+            }
+            _ = &mut deadline, if !killed => {
+                kill_process_group(&mut child);
+                completion.timed_out = true;
+                grace.as_mut().reset(tokio::time::Instant::now() + KILL_GRACE);
+            }
+            _ = &mut grace, if killed => break,
+            line = read_line(&mut stdout, &mut stdout_line), if stdout_open => {
+                match line? {
+                    Some(line) => on_line(Stream::Stdout, &line),
+                    None => stdout_open = false,
+                }
+            }
+            line = read_line(&mut stderr, &mut stderr_line), if stderr_open => {
+                match line? {
+                    Some(line) => on_line(Stream::Stderr, &line),
+                    None => stderr_open = false,
+                }
+            }
+        }
+    }
+
+    let killed = completion.cancelled || completion.timed_out;
+    let status = tokio::select! {
+        status = child.wait() => status?,
+        _ = cancel.cancelled(), if !killed => {
+            kill_process_group(&mut child);
+            completion.cancelled = true;
+            child.wait().await?
+        }
+        _ = &mut deadline, if !killed => {
+            kill_process_group(&mut child);
+            completion.timed_out = true;
+            child.wait().await?
+        }
+    };
+    completion.exit_code = status.code().unwrap_or(-1);
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        completion.signal = status.signal();
+    }
+    completion.elapsed = started.elapsed();
+    Ok(completion)
+}
+
+/// Kills `child` and every process in its process group.
+fn kill_process_group(child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        kill_group(pid);
+        return;
+    }
+    let _ = child.start_kill();
+}
+
+/// Kills every process in the process group led by `pid`, as started by
+/// [`spawn`].
+#[cfg(unix)]
+pub fn kill_group(pid: u32) {
+    // SAFETY: kill(2) has no memory-safety preconditions; a negative pid
+    // addresses the process group led by the child.
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// Reads the next line, including its trailing newline, or `None` at EOF.
+/// Lines longer than [`MAX_LINE_BYTES`] are returned in pieces. Partial lines
+/// stay in `buf` if the future is dropped, so this is safe to use in
+/// `select!`.
+async fn read_line<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    buf: &mut Vec<u8>,
+) -> std::io::Result<Option<String>> {
+    let room = MAX_LINE_BYTES.saturating_sub(buf.len()).max(1) as u64;
+    let n = (&mut *reader).take(room).read_until(b'\n', buf).await?;
+    if n == 0 && buf.is_empty() {
+        return Ok(None);
+    }
+    // Keep a character split by the length cap for the next piece.
+    let end = match std::str::from_utf8(buf) {
+        Err(e) if n > 0 && !buf.ends_with(b"\n") && e.error_len().is_none() => {
+            e.valid_up_to().max(1)
+        }
+        _ => buf.len(),
+    };
+    let line = String::from_utf8_lossy(&buf[..end]).to_string();
+    buf.drain(..end);
+    Ok(Some(line))
+}
@@ -0,0 +1,83 @@
+use rmcp::schemars;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+use crate::script::raw_string;
+
+/// How `exec` returns the final value of a script's pipeline.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Only the text nu prints, as in an interactive shell.
+    #[default]
+    Text,
+    /// The final value as JSON data.
+    Json,
+    /// The final value as a NUON string, keeping nushell types such as
+    /// durations and file sizes.
+    Nuon,
+    /// The final value rendered as an expanded table without colors.
+    Table,
+}
+
+impl OutputFormat {
+    /// Returns the pipeline that encodes the final value and saves it to
+    /// `path`, or `None` for `Text`, where the value is printed as usual.
+    pub fn capture(&self, path: &Path) -> Option<String> {
+        let encoder = match self {
+            OutputFormat::Text => return None,
+            OutputFormat::Json => "to json --raw",
+            OutputFormat::Nuon => "to nuon",
+            OutputFormat::Table => "table --expand | ansi strip",
+        };
+        Some(format!(
+            "{encoder} | save --raw --force {}",
+            raw_string(&path.to_string_lossy())
+        ))
+    }
+
+    /// Decodes a value saved by the `capture` pipeline.
+    pub fn decode(&self, raw: String) -> Value {
+        match self {
+            OutputFormat::Json => serde_json::from_str(&raw).unwrap_or(Value::String(raw)),
+            _ => Value::String(raw),
+        }
+    }
+}
+
+/// How `exec` decodes its `input` before handing it to the script as `$in`.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum InputFormat {
+    /// A plain string.
+    #[default]
+    Text,
+    /// JSON, decoded into nushell values.
+    Json,
+    /// NUON, decoded into nushell values.
+    Nuon,
+    /// CSV with a header row, decoded into a table.
+    Csv,
+}
+
+impl InputFormat {
+    /// The file name to store input under; `open` decodes it by extension.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            InputFormat::Text => "input.txt",
+            InputFormat::Json => "input.json",
+            InputFormat::Nuon => "input.nuon",
+            InputFormat::Csv => "input.csv",
+        }
+    }
+
+    /// Returns the pipeline that reads input stored at `path`.
+    pub fn reader(&self, path: &Path) -> String {
+        format!("open {}", raw_string(&path.to_string_lossy()))
+    }
+}
@@ -0,0 +1,281 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::exec::{self, Stream};
+
+/// Bytes of output kept per job; older lines are dropped once it fills up.
+const JOB_BUFFER_BYTES: usize = 1024 * 1024;
+
+/// Finished jobs kept for `job_status` and `job_output`; starting a job
+/// forgets the oldest ones beyond this.
+const MAX_FINISHED_JOBS: usize = 32;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Exited { exit_code: i32 },
+    Killed,
+    Failed { error: String },
+}
+
+/// Bounded buffer of output lines. Lines are addressed by their absolute
+/// index since the job started, so readers can keep tailing with an offset
+/// even after older lines were dropped.
+#[derive(Default)]
+struct OutputBuffer {
+    lines: VecDeque<(Stream, String)>,
+    /// Absolute index of `lines[0]`.
+    first: u64,
+    bytes: usize,
+    /// Bytes written to stdout and stderr since the job started.
+    stdout_bytes: usize,
+    stderr_bytes: usize,
+}
+
+impl OutputBuffer {
+    fn push(&mut self, stream: Stream, line: &str) {
+        match stream {
+            Stream::Stdout => self.stdout_bytes += line.len(),
+            Stream::Stderr => self.stderr_bytes += line.len(),
+        }
+        self.bytes += line.len();
+        self.lines.push_back((stream, line.to_string()));
+        while self.bytes > JOB_BUFFER_BYTES && self.lines.len() > 1 {
+            if let Some((_, dropped)) = self.lines.pop_front() {
+                self.bytes -= dropped.len();
+                self.first += 1;
+            }
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.first + self.lines.len() as u64
+    }
+
+    fn read(&self, offset: u64, limit: usize) -> Value {
+        let start = offset.max(self.first);
+        let lines: Vec<_> = self
+            .lines
+            .iter()
+            .skip((start - self.first) as usize)
+            .take(limit)
+            .map(|(stream, text)| {
+                json!({
+                    "stream": match stream {
+                        Stream::Stdout => "stdout",
+                        Stream::Stderr => "stderr",
+                    },
+                    "text": text,
+                })
+            })
+            .collect();
+
+        json!({
+            "next_offset": start + lines.len() as u64,
+            "dropped": start - offset,
+            "total_lines": self.total(),
+            "lines": lines,
+        })
+    }
+}
+
+/// A script running in the background under a supervised `nu` child.
+pub struct Job {
+    id: String,
+    script: String,
+    started: Instant,
+    cancel: CancellationToken,
+    output: Mutex<OutputBuffer>,
+    state: watch::Receiver<JobState>,
+    elapsed: Mutex<Option<Duration>>,
+    /// The job's process group while it runs.
+    pgid: Mutex<Option<u32>>,
+}
+
+impl Job {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// How long the job ran, or has been running.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+            .lock()
+            .unwrap()
+            .unwrap_or_else(|| self.started.elapsed())
+    }
+
+    /// Bytes the job has written to stdout and stderr, including lines no
+    /// longer buffered.
+    pub fn output_bytes(&self) -> (usize, usize) {
+        let output = self.output.lock().unwrap();
+        (output.stdout_bytes, output.stderr_bytes)
+    }
+
+    pub fn status(&self) -> Value {
+        let mut status = json!({
+            "job_id": self.id,
+            "script": self.script.chars().take(100).collect::<String>(),
+            "elapsed_ms": self.elapsed().as_millis() as u64,
+            "total_lines": self.output.lock().unwrap().total(),
+        });
+        if let (Value::Object(status), Ok(Value::Object(state))) =
+            (&mut status, serde_json::to_value(&*self.state.borrow()))
+        {
+            status.extend(state);
+        }
+        status
+    }
+
+    pub fn read(&self, offset: u64, limit: usize) -> Value {
+        self.output.lock().unwrap().read(offset, limit)
+    }
+
+    fn is_running(&self) -> bool {
+        matches!(*self.state.borrow(), JobState::Running)
+    }
+
+    /// Waits until the job is no longer running, or `timeout` passes.
+    pub async fn wait(&self, timeout: Duration) {
+        let mut state = self.state.clone();
+        let _ = tokio::time::timeout(
+            timeout,
+            state.wait_for(|state| !matches!(state, JobState::Running)),
+        )
+        .await;
+    }
+
+    /// Waits until the job is no longer running and returns how it ended.
+    pub async fn finished(&self) -> JobState {
+        let mut state = self.state.clone();
+        let finished = state
+            .wait_for(|state| !matches!(state, JobState::Running))
+            .await
+            .map(|state| state.clone());
+        finished.unwrap_or_else(|_| self.state.borrow().clone())
+    }
+
+    /// Kills the job's process group and waits for it to exit.
+    pub async fn kill(&self) {
+        self.cancel.cancel();
+        let mut state = self.state.clone();
+        let _ = state
+            .wait_for(|state| !matches!(state, JobState::Running))
+            .await;
+    }
+}
+
+/// Tracks the jobs started through `job_start`.
+pub struct JobManager {
+    next_id: AtomicU64,
+    jobs: Mutex<HashMap<String, Arc<Job>>>,
+}
+
+impl JobManager {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            jobs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn start(&self, cmd: Command, script: &str) -> Arc<Job> {
+        let id = format!("j{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        let (state_tx, state_rx) = watch::channel(JobState::Running);
+        let child = exec::spawn(cmd);
+        let job = Arc::new(Job {
+            id: id.clone(),
+            script: script.to_string(),
+            started: Instant::now(),
+            cancel: CancellationToken::new(),
+            output: Mutex::new(OutputBuffer::default()),
+            state: state_rx,
+            elapsed: Mutex::new(None),
+            pgid: Mutex::new(child.as_ref().ok().and_then(|child| child.id())),
+        });
+
+        let supervised = job.clone();
+        tokio::spawn(async move {
+            let job = supervised;
+            let result = match child {
+                Ok(child) => {
+                    exec::supervise(child, None, &job.cancel, |stream, line| {
+                        job.output.lock().unwrap().push(stream, line)
+                    })
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            job.pgid.lock().unwrap().take();
+
+            let state = match result {
+                Ok(completion) => {
+                    *job.elapsed.lock().unwrap() = Some(completion.elapsed);
+                    if completion.cancelled {
+                        JobState::Killed
+                    } else {
+                        JobState::Exited {
+                            exit_code: completion.exit_code,
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("Job {} failed to run: {}", job.id, e);
+                    JobState::Failed {
+                        error: e.to_string(),
+                    }
+                }
+            };
+            info!("Job {} finished: {:?}", job.id, state);
+            let _ = state_tx.send(state);
+        });
+
+        let mut jobs = self.jobs.lock().unwrap();
+        evict_finished(&mut jobs);
+        jobs.insert(id, job.clone());
+        job
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Job>> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+}
+
+/// Forgets the oldest finished jobs beyond [`MAX_FINISHED_JOBS`].
+fn evict_finished(jobs: &mut HashMap<String, Arc<Job>>) {
+    let mut finished: Vec<_> = jobs
+        .values()
+        .filter(|job| !job.is_running())
+        .map(|job| (job.started, job.id.clone()))
+        .collect();
+    if finished.len() <= MAX_FINISHED_JOBS {
+        return;
+    }
+    finished.sort();
+    for (_, id) in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+        jobs.remove(id);
+    }
+}
+
+impl Drop for JobManager {
+    fn drop(&mut self) {
+        // The supervising tasks may never run again once the runtime shuts
+        // down, so kill the process groups here rather than leave it to them.
+        for job in self.jobs.get_mut().unwrap().values() {
+            job.cancel.cancel();
+            #[cfg(unix)]
+            if let Some(pgid) = *job.pgid.lock().unwrap() {
+                exec::kill_group(pgid);
+            }
+        }
+    }
+}
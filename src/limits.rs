@@ -0,0 +1,170 @@
+//! Per-execution resource limits, applied with `setrlimit` in the spawned nu
+//! process and inherited by everything it runs.
+
+use serde::Serialize;
+use std::io;
+use tokio::process::Command;
+
+use crate::exec::Completion;
+
+const MIB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResourceLimits {
+    /// Address space of each process, in MiB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_mb: Option<u64>,
+    /// CPU time of each process, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_seconds: Option<u64>,
+    /// Processes of the server's user, counted across the whole system.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub processes: Option<u64>,
+    /// Open file descriptors of each process.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_files: Option<u64>,
+    /// Size of any file a process writes, in MiB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_size_mb: Option<u64>,
+}
+
+/// A limit that a failed run ran into.
+#[derive(Debug, Clone, Copy)]
+pub struct Exceeded {
+    pub limit: &'static str,
+    /// False if the process was killed in a way the limit could have caused,
+    /// but so could something else.
+    pub certain: bool,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.memory_mb.is_none()
+            && self.cpu_seconds.is_none()
+            && self.processes.is_none()
+            && self.open_files.is_none()
+            && self.file_size_mb.is_none()
+    }
+
+    #[cfg(unix)]
+    pub fn apply(&self, cmd: &mut Command) -> io::Result<()> {
+        // (resource, soft limit, hard limit). The CPU hard limit is a second
+        // later so the process gets SIGXCPU, which identifies the limit,
+        // before SIGKILL. Values too large to represent saturate rather than
+        // wrapping around to a tiny limit.
+        let bytes = |mb: u64| mb.saturating_mul(MIB);
+        let limits: Vec<_> = [
+            (
+                libc::RLIMIT_AS,
+                self.memory_mb.map(|mb| (bytes(mb), bytes(mb))),
+            ),
+            (
+                libc::RLIMIT_CPU,
+                self.cpu_seconds.map(|s| (s, s.saturating_add(1))),
+            ),
+            (libc::RLIMIT_NPROC, self.processes.map(|n| (n, n))),
+            (libc::RLIMIT_NOFILE, self.open_files.map(|n| (n, n))),
+            (
+                libc::RLIMIT_FSIZE,
+                self.file_size_mb.map(|mb| (bytes(mb), bytes(mb))),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(resource, limit)| {
+            limit.map(|(soft, hard)| {
+                let limit = libc::rlimit {
+                    rlim_cur: soft as libc::rlim_t,
+                    rlim_max: hard as libc::rlim_t,
+                };
+                (resource, limit)
+            })
+        })
+        .collect();
+
+        // SAFETY: setrlimit is async-signal-safe, and the limits were
+        // prepared before forking.
+        unsafe {
+            cmd.pre_exec(move || {
+                for (resource, limit) in &limits {
+                    if libc::setrlimit(*resource, limit) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self, _cmd: &mut Command) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Resource limits are only supported on Unix",
+        ))
+    }
+
+    /// Names the limit a failed run most likely ran into, judging by how the
+    /// process ended and what it printed. Only the signals the limits send
+    /// are certain.
+    pub fn exceeded(&self, completion: &Completion, stderr: &str) -> Option<Exceeded> {
+        if completion.exit_code == 0 || completion.timed_out || completion.cancelled {
+            return None;
+        }
+        let exceeded = |limit, certain| Some(Exceeded { limit, certain });
+        #[cfg(unix)]
+        match completion.signal {
+            Some(libc::SIGXCPU) if self.cpu_seconds.is_some() => {
+                return exceeded("cpu_seconds", true)
+            }
+            Some(libc::SIGXFSZ) if self.file_size_mb.is_some() => {
+                return exceeded("file_size_mb", true)
+            }
+            // The hard CPU limit kills with SIGKILL, but only processes that
+            // survived SIGXCPU at the soft limit. The OOM killer or another
+            // process may just as well have sent it.
+            Some(libc::SIGKILL) if self.cpu_seconds.is_some() => {
+                return exceeded("cpu_seconds", false)
+            }
+            _ => {}
+        }
+
+        // Otherwise nu, or a command it ran, may report the failure on
+        // stderr, though the same messages come up without any limit.
+        let checks = [
+            (
+                self.cpu_seconds.is_some(),
+                "cpu_seconds",
+                &["CPU time limit exceeded", "SIGXCPU"][..],
+            ),
+            (
+                self.file_size_mb.is_some(),
+                "file_size_mb",
+                &["File too large", "File size limit exceeded", "SIGXFSZ"][..],
+            ),
+            (
+                self.memory_mb.is_some(),
+                "memory_mb",
+                &[
+                    "memory allocation of",
+                    "Cannot allocate memory",
+                    "out of memory",
+                ][..],
+            ),
+            (
+                self.open_files.is_some(),
+                "open_files",
+                &["Too many open files"][..],
+            ),
+            (
+                self.processes.is_some(),
+                "processes",
+                &["Resource temporarily unavailable"][..],
+            ),
+        ];
+        checks
+            .into_iter()
+            .find(|(set, _, patterns)| *set && patterns.iter().any(|p| stderr.contains(p)))
+            .and_then(|(_, limit, _)| exceeded(limit, false))
+    }
+}
@@ -1,16 +1,68 @@
+mod audit;
+mod auth;
+mod check;
+mod commands;
+mod config;
+mod diagnostics;
+mod exec;
+mod format;
+mod jobs;
+mod limits;
+mod module_tools;
+mod nu_version;
+mod origin;
+mod output;
+mod policy;
+mod reload;
+mod sandbox;
+mod script;
+mod session;
 mod tools;
+#[cfg(unix)]
+mod unix_socket;
 
 use anyhow::Result;
+use audit::{AuditLog, ScriptMode};
+use auth::{Authenticator, StaticTokens};
 use clap::Parser;
+use config::{Settings, Transport};
+use module_tools::ToolsModule;
+use origin::OriginPolicy;
+use policy::Policy;
+use reload::Live;
 use rmcp::{
-    model::*, service::ServerInitializeError, transport::stdio, ErrorData as McpError, ServiceExt,
+    model::*,
+    service::ServerInitializeError,
+    transport::{
+        stdio,
+        streamable_http_server::{
+            session::local::LocalSessionManager, StreamableHttpServerConfig, StreamableHttpService,
+        },
+    },
+    ErrorData as McpError, ServiceExt,
 };
+use sandbox::SandboxConfig;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
 use tools::NuServer;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Path to a TOML config file; CLI flags override its values
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Profile of the config file to apply on top of its top-level values
+    #[arg(long, requires = "config")]
+    profile: Option<String>,
+
+    /// The nu binary to run scripts with [default: nu, looked up on PATH]
+    #[arg(long = "nu-bin")]
+    nu_bin: Option<PathBuf>,
+
     /// Path to custom nushell config.nu file
     #[arg(long = "nu-config")]
     nu_config: Option<String>,
@@ -18,22 +70,351 @@ struct Args {
     /// Path to custom nushell env.nu file
     #[arg(long = "nu-env-config")]
     nu_env_config: Option<String>,
+
+    /// Path to a nushell module whose exported commands are exposed as tools
+    #[arg(long = "tools-module")]
+    tools_module: Option<PathBuf>,
+
+    /// Path to a TOML policy file of commands scripts may or may not use
+    #[arg(long)]
+    policy: Option<PathBuf>,
+
+    /// Refuse scripts that write files or send data over the network, and run
+    /// nu with a read-only filesystem (Linux only)
+    #[arg(long = "read-only")]
+    read_only: bool,
+
+    /// External binary that scripts may run in read-only mode (repeatable)
+    #[arg(long = "allow-external")]
+    allow_external: Vec<String>,
+
+    /// Run scripts in a Landlock sandbox (Linux only)
+    #[arg(long)]
+    sandbox: bool,
+
+    /// Path the sandbox may read and execute (repeatable; default: /)
+    #[arg(long = "sandbox-read")]
+    sandbox_read: Vec<PathBuf>,
+
+    /// Path the sandbox may write (repeatable)
+    #[arg(long = "sandbox-write")]
+    sandbox_write: Vec<PathBuf>,
+
+    /// Forbid opening network sockets inside the sandbox
+    #[arg(long = "sandbox-no-network")]
+    sandbox_no_network: bool,
+
+    /// Directory that exec's `cwd` must be inside (repeatable; default: any)
+    #[arg(long = "allowed-root")]
+    allowed_roots: Vec<PathBuf>,
+
+    /// Most bytes of stdout, and of stderr, returned by exec before the
+    /// middle is cut out [default: 65536]
+    #[arg(long = "max-output-bytes")]
+    max_output_bytes: Option<usize>,
+
+    /// Address space limit for each process a script runs, in MiB
+    #[arg(long = "limit-memory-mb")]
+    limit_memory_mb: Option<u64>,
+
+    /// CPU time limit for each process a script runs, in seconds
+    #[arg(long = "limit-cpu-seconds")]
+    limit_cpu_seconds: Option<u64>,
+
+    /// Maximum number of processes of the server's user while a script runs
+    #[arg(long = "limit-processes")]
+    limit_processes: Option<u64>,
+
+    /// Maximum number of open files for each process a script runs
+    #[arg(long = "limit-open-files")]
+    limit_open_files: Option<u64>,
+
+    /// Maximum size of a file written by a script, in MiB
+    #[arg(long = "limit-file-size-mb")]
+    limit_file_size_mb: Option<u64>,
+
+    /// Transport to serve MCP over [default: stdio]
+    #[arg(long, value_enum)]
+    transport: Option<Transport>,
+
+    /// Address to listen on with `--transport http` [default: 127.0.0.1:8000]
+    #[arg(long)]
+    bind: Option<SocketAddr>,
+
+    /// File of bearer tokens accepted by `--transport http`, one
+    /// `token[=tool,...]` entry per line
+    #[arg(long = "auth-tokens")]
+    auth_tokens: Option<PathBuf>,
+
+    /// Extra `Host` header value accepted by `--transport http`, besides
+    /// loopback names and the bind address (repeatable)
+    #[arg(long = "allowed-host")]
+    allowed_hosts: Vec<String>,
+
+    /// Extra `Origin` accepted by `--transport http`, such as
+    /// `https://app.example.com`, besides loopback origins (repeatable)
+    #[arg(long = "allowed-origin")]
+    allowed_origins: Vec<String>,
+
+    /// Append a JSON Lines record of every script run to this file
+    #[arg(long = "audit-log")]
+    audit_log: Option<PathBuf>,
+
+    /// Size at which the audit log is rotated, in bytes [default: 10485760]
+    #[arg(long = "audit-log-max-bytes")]
+    audit_log_max_bytes: Option<u64>,
+
+    /// Number of rotated audit logs to keep [default: 5]
+    #[arg(long = "audit-log-keep")]
+    audit_log_keep: Option<usize>,
+
+    /// How scripts are written to the audit log [default: full]
+    #[arg(long = "audit-scripts", value_enum)]
+    audit_scripts: Option<ScriptMode>,
+
+    /// Reload the configuration when the config, policy, tools module or nu
+    /// config files change
+    #[arg(long)]
+    watch: bool,
+
+    /// Serve clients on a Unix domain socket at this path, one per connection
+    #[cfg(unix)]
+    #[arg(long = "listen-unix")]
+    listen_unix: Option<PathBuf>,
+}
+
+impl Args {
+    /// The settings given on the command line.
+    fn settings(&self) -> Settings {
+        fn flag(set: bool) -> Option<bool> {
+            set.then_some(true)
+        }
+        fn list<T: Clone>(values: &[T]) -> Option<Vec<T>> {
+            (!values.is_empty()).then(|| values.to_vec())
+        }
+        Settings {
+            nu_bin: self.nu_bin.clone(),
+            nu_config: self.nu_config.clone(),
+            nu_env_config: self.nu_env_config.clone(),
+            tools_module: self.tools_module.clone(),
+            policy: self.policy.clone(),
+            read_only: flag(self.read_only),
+            allow_external: list(&self.allow_external),
+            sandbox: flag(self.sandbox),
+            sandbox_read: list(&self.sandbox_read),
+            sandbox_write: list(&self.sandbox_write),
+            sandbox_no_network: flag(self.sandbox_no_network),
+            allowed_roots: list(&self.allowed_roots),
+            max_output_bytes: self.max_output_bytes,
+            limit_memory_mb: self.limit_memory_mb,
+            limit_cpu_seconds: self.limit_cpu_seconds,
+            limit_processes: self.limit_processes,
+            limit_open_files: self.limit_open_files,
+            limit_file_size_mb: self.limit_file_size_mb,
+            transport: self.transport,
+            bind: self.bind,
+            auth_tokens: self.auth_tokens.clone(),
+            allowed_hosts: list(&self.allowed_hosts),
+            allowed_origins: list(&self.allowed_origins),
+            audit_log: self.audit_log.clone(),
+            audit_log_max_bytes: self.audit_log_max_bytes,
+            audit_log_keep: self.audit_log_keep,
+            audit_scripts: self.audit_scripts,
+            watch: flag(self.watch),
+            #[cfg(unix)]
+            listen_unix: self.listen_unix.clone(),
+            ..Default::default()
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    // Initialize tracing
+    let settings = load_settings(&args)?;
+
+    // Initialize tracing. Logs go to stderr so they can't corrupt the stdio
+    // transport.
     tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(settings.log())),
+        )
         .init();
 
     // Create and start the Nushell MCP server
+    let mut server = build_server(&settings, None).await?;
+    if settings.watch == Some(true) {
+        let live = Arc::new(Live::new(server.clone()));
+        server.set_live(live.clone());
+        let files = watched_files(&args, &settings);
+        let args = Arc::new(args);
+        let startup = Arc::new(settings.clone());
+        let current = live.clone();
+        tokio::spawn(reload::watch(live, files, move || {
+            let args = args.clone();
+            let startup = startup.clone();
+            let audit = current.server().audit_log();
+            async move {
+                let reloaded = load_settings(&args)?;
+                for (name, changed) in startup.restart_required(&reloaded) {
+                    if changed {
+                        tracing::warn!("Changes to {} take effect after a restart", name);
+                    }
+                }
+                let server = build_server(&reloaded, audit.as_deref()).await?;
+                Ok((server, watched_files(&args, &reloaded)))
+            }
+        }));
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = &settings.listen_unix {
+        return unix_socket::serve(server, path).await;
+    }
+
+    match settings.transport.unwrap_or(Transport::Stdio) {
+        Transport::Stdio => serve_stdio(server).await,
+        Transport::Http => {
+            let authenticator = StaticTokens::load(settings.auth_tokens.as_deref())?
+                .map(|tokens| Arc::new(tokens) as Arc<dyn Authenticator>);
+            let origins = OriginPolicy::new(
+                settings.bind(),
+                settings.allowed_hosts.as_deref().unwrap_or_default(),
+                settings.allowed_origins.as_deref().unwrap_or_default(),
+            );
+            serve_http(server, settings.bind(), authenticator, origins).await
+        }
+    }
+}
+
+/// Merges the config file, if any, with the command line.
+fn load_settings(args: &Args) -> Result<Settings> {
+    let mut settings = match &args.config {
+        Some(path) => Settings::load(path, args.profile.as_deref())?,
+        None => Settings::default(),
+    };
+    settings.overlay(args.settings());
+    settings.validate()?;
+    Ok(settings)
+}
+
+/// The files `--watch` reloads the configuration on.
+fn watched_files(args: &Args, settings: &Settings) -> Vec<PathBuf> {
+    args.config
+        .iter()
+        .cloned()
+        .chain(settings.watched_files())
+        .filter_map(|path| std::path::absolute(path).ok())
+        .collect()
+}
+
+/// Builds a server for `settings`. `audit` is the log of the server being
+/// replaced, if any, which keeps writing the same file.
+async fn build_server(settings: &Settings, audit: Option<&AuditLog>) -> Result<NuServer> {
+    let mut server = NuServer::new(settings.nu_config.clone(), settings.nu_env_config.clone());
+    let nu_bin = settings.nu_bin();
+    let version = nu_version::probe(&nu_bin).await?;
+    tracing::info!("Using nu {} at {}", version, nu_bin.display());
+    server.set_nu_bin(nu_bin);
+    server.set_nu_version(version);
+    if let Some(env) = &settings.env {
+        server.set_env(env.clone());
+    }
+    server.set_timeouts(settings.default_timeout(), settings.max_timeout());
+    if let Some(path) = &settings.tools_module {
+        let module = ToolsModule::new(path)
+            .map_err(|e| anyhow::anyhow!("Invalid tools module {}: {}", path.display(), e))?;
+        server.load_tools_module(module).await?;
+    }
+    if let Some(path) = &settings.policy {
+        server.set_policy(Policy::load(path)?);
+    }
+    if settings.sandbox == Some(true) {
+        let mut sandbox = SandboxConfig {
+            write: settings.sandbox_write.clone().unwrap_or_default(),
+            network: settings.sandbox_no_network != Some(true),
+            ..Default::default()
+        };
+        if let Some(read) = &settings.sandbox_read {
+            sandbox.read = read.clone();
+        }
+        server.set_sandbox(sandbox)?;
+    }
+    if settings.read_only == Some(true) {
+        server.set_read_only(settings.allow_external.as_deref().unwrap_or_default())?;
+    }
+    server.set_allowed_roots(settings.allowed_roots.as_deref().unwrap_or_default())?;
+    server.set_max_output_bytes(settings.max_output_bytes());
+    let limits = settings.limits();
+    if !limits.is_empty() {
+        server.set_limits(limits);
+    }
+    if let Some(path) = &settings.audit_log {
+        let (max_bytes, keep, scripts) = (
+            settings.audit_log_max_bytes(),
+            settings.audit_log_keep(),
+            settings.audit_scripts.unwrap_or_default(),
+        );
+        server.set_audit_log(match audit {
+            Some(audit) => audit.reopen(path, max_bytes, keep, scripts)?,
+            None => AuditLog::open(path, max_bytes, keep, scripts)?,
+        });
+    }
+    Ok(server)
+}
+
+async fn serve_http(
+    server: NuServer,
+    bind: SocketAddr,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    origins: OriginPolicy,
+) -> Result<()> {
+    // Each client session gets its own server, sharing the tools and config
+    // but not nushell sessions or jobs.
+    let service = StreamableHttpService::new(
+        move || Ok(server.new_client()),
+        Arc::new(LocalSessionManager::default()),
+        StreamableHttpServerConfig::default(),
+    );
+    let mut router = axum::Router::new().nest_service("/mcp", service);
+    match authenticator {
+        Some(authenticator) => {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                authenticator,
+                auth::middleware,
+            ));
+        }
+        None if !bind.ip().is_loopback() => {
+            tracing::warn!(
+                "Serving on {} without authentication: anyone who can reach it can run code",
+                bind
+            );
+        }
+        None => {}
+    }
+    // Added last so it runs first: requests from unexpected hosts or origins
+    // never reach authentication or the MCP service.
+    router = router.layer(axum::middleware::from_fn_with_state(
+        Arc::new(origins),
+        origin::middleware,
+    ));
+
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    tracing::info!("Serving MCP over streamable HTTP at http://{}/mcp", bind);
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await?;
+
+    Ok(())
+}
+
+async fn serve_stdio(server: NuServer) -> Result<()> {
     loop {
-        match NuServer::new(args.nu_config.clone(), args.nu_env_config.clone())
-            .serve(stdio())
-            .await
-        {
+        match server.clone().serve(stdio()).await {
             Ok(service) => {
                 service.waiting().await?;
                 break;
@@ -0,0 +1,257 @@
+//! Exposes the exported commands of a nushell module as MCP tools.
+
+use rmcp::model::JsonObject;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+
+use crate::script::{nu_value, raw_string};
+
+/// A command exported by the tools module.
+#[derive(Debug, Clone)]
+pub struct ModuleCommand {
+    /// Name of the MCP tool.
+    pub tool_name: String,
+    /// Name of the command once the module is imported with `use`.
+    pub command: String,
+    pub description: String,
+    params: Vec<Param>,
+}
+
+#[derive(Debug, Clone)]
+struct Param {
+    name: String,
+    kind: ParamKind,
+    shape: String,
+    optional: bool,
+    description: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParamKind {
+    Positional,
+    Rest,
+    Named,
+    Switch,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScopeCommand {
+    name: String,
+    description: String,
+    extra_description: String,
+    signatures: Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScopeParam {
+    parameter_name: String,
+    parameter_type: String,
+    syntax_shape: String,
+    #[serde(default)]
+    is_optional: bool,
+    #[serde(default)]
+    description: String,
+}
+
+/// A nushell module given with `--tools-module`, either a `.nu` file or a
+/// directory with a `mod.nu`.
+#[derive(Debug, Clone)]
+pub struct ToolsModule {
+    path: PathBuf,
+    name: String,
+}
+
+impl ToolsModule {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        let path = path.canonicalize()?;
+        let name = if path.is_dir() {
+            path.file_name()
+        } else {
+            path.file_stem()
+        }
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+        Ok(Self { path, name })
+    }
+
+    fn use_statement(&self) -> String {
+        format!("use {}\n", raw_string(&self.path.to_string_lossy()))
+    }
+
+    /// Script that imports the module and prints its custom commands as JSON.
+    pub fn introspect_script(&self) -> String {
+        format!(
+            r#"{}scope commands | where type == 'custom' | each {{|c| {{
+  name: $c.name
+  description: ($c.description? | default $c.usage? | default '')
+  extra_description: ($c.extra_description? | default $c.extra_usage? | default '')
+  signatures: $c.signatures
+}} }} | to json --raw"#,
+            self.use_statement()
+        )
+    }
+
+    /// Picks the module's exported commands out of the introspection output.
+    pub fn commands(&self, introspected: Value) -> Result<Vec<ModuleCommand>, String> {
+        let scope: Vec<ScopeCommand> =
+            serde_json::from_value(introspected).map_err(|e| e.to_string())?;
+        let prefix = format!("{} ", self.name);
+
+        scope
+            .into_iter()
+            .filter(|c| c.name == self.name || c.name.starts_with(&prefix))
+            .map(|c| {
+                let subcommand = c.name.strip_prefix(&prefix).unwrap_or(&self.name);
+                let tool_name = subcommand
+                    .chars()
+                    .map(|ch| {
+                        if ch.is_ascii_alphanumeric() || ch == '-' {
+                            ch
+                        } else {
+                            '_'
+                        }
+                    })
+                    .collect();
+
+                let params = c
+                    .signatures
+                    .values()
+                    .next()
+                    .cloned()
+                    .map(serde_json::from_value::<Vec<ScopeParam>>)
+                    .transpose()
+                    .map_err(|e| format!("{}: {e}", c.name))?
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|p| {
+                        let kind = match p.parameter_type.as_str() {
+                            "positional" => ParamKind::Positional,
+                            "rest" => ParamKind::Rest,
+                            "named" => ParamKind::Named,
+                            "switch" => ParamKind::Switch,
+                            _ => return None,
+                        };
+                        // Every command has an implicit --help switch.
+                        if kind == ParamKind::Switch && p.parameter_name == "help" {
+                            return None;
+                        }
+                        Some(Param {
+                            name: p.parameter_name,
+                            kind,
+                            shape: p.syntax_shape,
+                            optional: p.is_optional || kind != ParamKind::Positional,
+                            description: p.description,
+                        })
+                    })
+                    .collect();
+
+                let description = match c.extra_description.is_empty() {
+                    true => c.description,
+                    false => format!("{}\n\n{}", c.description, c.extra_description),
+                };
+
+                Ok(ModuleCommand {
+                    tool_name,
+                    command: c.name,
+                    description,
+                    params,
+                })
+            })
+            .collect()
+    }
+
+    /// Builds the script that runs `command` with the arguments of a tool
+    /// call. Every value is passed as a literal, never spliced in as source.
+    pub fn invocation(
+        &self,
+        command: &ModuleCommand,
+        arguments: &JsonObject,
+    ) -> Result<String, String> {
+        if let Some(unknown) = arguments
+            .keys()
+            .find(|key| !command.params.iter().any(|p| &p.name == *key))
+        {
+            return Err(format!("Unknown argument: {unknown}"));
+        }
+
+        let mut positional = Vec::new();
+        let mut flags = Vec::new();
+        for param in &command.params {
+            let value = arguments.get(&param.name).filter(|v| !v.is_null());
+            match (param.kind, value) {
+                (ParamKind::Positional, Some(value)) => positional.push(nu_value(value)),
+                (ParamKind::Positional, None) if !param.optional => {
+                    return Err(format!("Missing required argument: {}", param.name));
+                }
+                (ParamKind::Positional, None) => positional.push("null".to_string()),
+                (ParamKind::Rest, Some(value)) => {
+                    let Value::Array(_) = value else {
+                        return Err(format!("Argument {} must be an array", param.name));
+                    };
+                    positional.push(format!("...{}", nu_value(value)));
+                }
+                (ParamKind::Named, Some(value)) => {
+                    flags.push(format!("--{} {}", param.name, nu_value(value)));
+                }
+                (ParamKind::Switch, Some(Value::Bool(true))) => {
+                    flags.push(format!("--{}", param.name));
+                }
+                _ => {}
+            }
+        }
+        // Optional positionals that weren't given at the end can be left out.
+        while positional.last().is_some_and(|p| p == "null") {
+            positional.pop();
+        }
+
+        let mut call = vec![command.command.clone()];
+        call.extend(flags);
+        call.extend(positional);
+        Ok(format!("{}{}", self.use_statement(), call.join(" ")))
+    }
+}
+
+impl ModuleCommand {
+    /// JSON Schema for the tool's arguments, generated from the signature.
+    pub fn input_schema(&self) -> JsonObject {
+        let mut properties = Map::new();
+        let mut required = Vec::new();
+
+        for param in &self.params {
+            let mut schema = match param.kind {
+                ParamKind::Switch => json!({ "type": "boolean" }),
+                ParamKind::Rest => json!({ "type": "array", "items": shape_schema(&param.shape) }),
+                _ => shape_schema(&param.shape),
+            };
+            if !param.description.is_empty() {
+                schema["description"] = json!(param.description);
+            }
+            if !param.optional {
+                required.push(json!(param.name));
+            }
+            properties.insert(param.name.clone(), schema);
+        }
+
+        let mut schema = JsonObject::new();
+        schema.insert("type".to_string(), json!("object"));
+        schema.insert("properties".to_string(), Value::Object(properties));
+        if !required.is_empty() {
+            schema.insert("required".to_string(), Value::Array(required));
+        }
+        schema
+    }
+}
+
+/// Maps a nushell syntax shape to a JSON Schema.
+fn shape_schema(shape: &str) -> Value {
+    match shape {
+        "int" => json!({ "type": "integer" }),
+        "float" | "number" => json!({ "type": "number" }),
+        "bool" | "boolean" => json!({ "type": "boolean" }),
+        "any" => json!({}),
+        s if s.starts_with("list") || s.starts_with("table") => json!({ "type": "array" }),
+        s if s.starts_with("record") => json!({ "type": "object" }),
+        _ => json!({ "type": "string" }),
+    }
+}
@@ -0,0 +1,121 @@
+//! Finds the nu binary and checks that its version is supported.
+
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Oldest supported nu version.
+pub const MIN_VERSION: NuVersion = NuVersion::new(0, 90, 0);
+/// First nu version that is no longer supported.
+pub const MAX_VERSION: NuVersion = NuVersion::new(1, 0, 0);
+
+/// How long `nu --version` may take.
+const PROBE_TIMEOUT_SECONDS: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NuVersion {
+    major: u64,
+    minor: u64,
+    patch: u64,
+}
+
+impl NuVersion {
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses the output of `nu --version`, such as `0.106.1`. Pre-release
+    /// and build suffixes are ignored.
+    pub fn parse(text: &str) -> Option<Self> {
+        let version = text.split_whitespace().next()?;
+        let version = version.split(['-', '+']).next()?;
+        let mut parts = version.split('.').map(|part| part.parse().ok());
+        let version = Self::new(
+            parts.next()??,
+            parts.next()??,
+            parts.next().unwrap_or(Some(0))?,
+        );
+        parts.next().is_none().then_some(version)
+    }
+
+    pub fn is_supported(&self) -> bool {
+        (MIN_VERSION..MAX_VERSION).contains(self)
+    }
+
+    /// The supported versions, for messages.
+    pub fn supported_range() -> String {
+        format!(">={MIN_VERSION}, <{MAX_VERSION}")
+    }
+}
+
+impl fmt::Display for NuVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl Serialize for NuVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Runs `nu_bin --version` and checks the version it reports, failing with a
+/// message saying what to fix if nu is missing, broken or unsupported.
+pub async fn probe(nu_bin: &Path) -> anyhow::Result<NuVersion> {
+    let mut cmd = Command::new(nu_bin);
+    cmd.arg("--version")
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true);
+    let output = match tokio::time::timeout(
+        Duration::from_secs(PROBE_TIMEOUT_SECONDS),
+        cmd.output(),
+    )
+    .await
+    {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => anyhow::bail!(
+            "nu binary '{}' not found. Install Nushell ({}) or point --nu-bin at it",
+            nu_bin.display(),
+            NuVersion::supported_range()
+        ),
+        Ok(Err(e)) => anyhow::bail!("Failed to run '{} --version': {}", nu_bin.display(), e),
+        Err(_) => anyhow::bail!(
+            "'{} --version' did not finish within {} seconds",
+            nu_bin.display(),
+            PROBE_TIMEOUT_SECONDS
+        ),
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        anyhow::bail!(
+            "'{} --version' failed ({}): {}",
+            nu_bin.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let version = NuVersion::parse(&stdout).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Unrecognized output from '{} --version': {}",
+            nu_bin.display(),
+            stdout.trim()
+        )
+    })?;
+    if !version.is_supported() {
+        anyhow::bail!(
+            "nu {} at '{}' is not supported; this server needs nu {}",
+            version,
+            nu_bin.display(),
+            NuVersion::supported_range()
+        );
+    }
+    Ok(version)
+}
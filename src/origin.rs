@@ -0,0 +1,128 @@
+//! `Origin` and `Host` validation for the HTTP transport.
+//!
+//! A web page can't read responses from another origin, but it can still send
+//! requests to a server on the user's machine, and with DNS rebinding make
+//! them look same-origin. Requests are therefore only served if their `Host`
+//! names this server and their `Origin`, when a browser sends one, is a
+//! loopback address or explicitly allowed. Others are rejected with HTTP 403.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::Value;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::auth;
+
+/// The hosts and origins the HTTP transport accepts requests from.
+#[derive(Debug, Clone, Default)]
+pub struct OriginPolicy {
+    /// Extra `Host` header values, with or without a port.
+    hosts: Vec<String>,
+    /// Extra `Origin` header values, such as `https://app.example.com`.
+    origins: Vec<String>,
+}
+
+impl OriginPolicy {
+    /// Accepts loopback hosts and origins, the address the server is bound to
+    /// unless it's a wildcard, and `hosts` and `origins`.
+    pub fn new(bind: SocketAddr, hosts: &[String], origins: &[String]) -> Self {
+        let mut policy = Self {
+            hosts: hosts.iter().map(|host| host.to_ascii_lowercase()).collect(),
+            origins: origins
+                .iter()
+                .map(|origin| origin.trim_end_matches('/').to_ascii_lowercase())
+                .collect(),
+        };
+        if !bind.ip().is_unspecified() {
+            policy.hosts.push(bind.ip().to_string());
+        }
+        policy
+    }
+
+    fn allows_host(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        let name = host_name(&host);
+        is_loopback(name) || self.hosts.iter().any(|h| *h == host || h == name)
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+        if self.origins.contains(&origin) {
+            return true;
+        }
+        match origin.split_once("://") {
+            Some(("http" | "https", authority)) => is_loopback(host_name(authority)),
+            _ => false,
+        }
+    }
+}
+
+/// The host name of a `host[:port]` authority, without brackets around an
+/// IPv6 address.
+fn host_name(authority: &str) -> &str {
+    if let Some(rest) = authority.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or_default();
+    }
+    match authority.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => authority,
+    }
+}
+
+fn is_loopback(name: &str) -> bool {
+    name == "localhost"
+        || name.ends_with(".localhost")
+        || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Axum middleware that rejects requests from hosts or origins the policy
+/// doesn't allow.
+pub async fn middleware(
+    State(policy): State<Arc<OriginPolicy>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(rejection) = check(&policy, &request) {
+        return rejection;
+    }
+    next.run(request).await
+}
+
+/// The response rejecting `request`, if the policy doesn't allow it.
+fn check(policy: &OriginPolicy, request: &Request) -> Option<Response> {
+    let header_value = |name| {
+        request
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap_or_default())
+    };
+    let host = header_value(header::HOST)
+        .or_else(|| request.uri().authority().map(|a| a.as_str()))
+        .unwrap_or_default();
+    if !policy.allows_host(host) {
+        warn!("Rejected request for host '{}'", host);
+        return Some(auth::rpc_error(
+            StatusCode::FORBIDDEN,
+            Value::Null,
+            auth::FORBIDDEN,
+            &format!("Host '{host}' is not allowed; add it with --allowed-host"),
+        ));
+    }
+    let origin = header_value(header::ORIGIN)?;
+    if !policy.allows_origin(origin) {
+        warn!("Rejected request from origin '{}'", origin);
+        return Some(auth::rpc_error(
+            StatusCode::FORBIDDEN,
+            Value::Null,
+            auth::FORBIDDEN,
+            &format!("Origin '{origin}' is not allowed; add it with --allowed-origin"),
+        ));
+    }
+    None
+}
@@ -0,0 +1,209 @@
+use rmcp::schemars;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Bytes of full output kept for `read_output`; the oldest outputs are
+/// dropped once it fills up.
+const STORE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Most bytes of each stream an execution keeps, so one run's output fits in
+/// the store.
+pub const CAPTURE_BYTES: usize = STORE_BYTES / 2;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    #[default]
+    Stdout,
+    Stderr,
+    /// The JSON-encoded `value` of a structured `exec` result.
+    Value,
+}
+
+/// Default for the most bytes of stdout, and of stderr, an `exec` result
+/// includes.
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024;
+
+/// Shortens `text` to about `max_bytes` by keeping its head and tail and
+/// replacing the middle with a marker line. The cut falls on line breaks
+/// where the kept parts have any. Returns the shortened text and the number
+/// of bytes left out, or `None` if `text` already fits.
+pub fn truncate(text: &str, max_bytes: usize) -> Option<(String, usize)> {
+    if text.len() <= max_bytes {
+        return None;
+    }
+    let head = &text[..floor_char_boundary(text, max_bytes / 2)];
+    let head = head.rfind('\n').map_or(head, |i| &head[..=i]);
+    let tail = &text[ceil_char_boundary(text, text.len() - (max_bytes - max_bytes / 2))..];
+    let tail = tail.find('\n').map_or(tail, |i| &tail[i + 1..]);
+
+    let omitted = text.len() - head.len() - tail.len();
+    let separator = if head.is_empty() || head.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    let shortened = format!(
+        "{head}{separator}[... {omitted} bytes truncated; use read_output to see them ...]\n{tail}"
+    );
+    Some((shortened, omitted))
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn ceil_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
+
+/// Collects the lines of an output stream, keeping at most about `limit`
+/// bytes: its head and tail, counting the bytes dropped in between.
+pub struct Capture {
+    limit: usize,
+    head: String,
+    tail: VecDeque<String>,
+    tail_bytes: usize,
+    total_bytes: usize,
+}
+
+impl Capture {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            head: String::new(),
+            tail: VecDeque::new(),
+            tail_bytes: 0,
+            total_bytes: 0,
+        }
+    }
+
+    pub fn push(&mut self, line: &str) {
+        self.total_bytes += line.len();
+        if self.tail.is_empty() && self.head.len() + line.len() <= self.limit / 2 {
+            self.head.push_str(line);
+            return;
+        }
+        self.tail.push_back(line.to_string());
+        self.tail_bytes += line.len();
+        while self.tail_bytes > self.limit - self.limit / 2 && self.tail.len() > 1 {
+            if let Some(dropped) = self.tail.pop_front() {
+                self.tail_bytes -= dropped.len();
+            }
+        }
+    }
+
+    /// Every byte pushed, kept or not.
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    /// The bytes dropped between head and tail, and the offset in
+    /// [`Capture::into_text`] where they're missing.
+    pub fn dropped(&self) -> Option<(usize, usize)> {
+        let dropped = self.total_bytes - self.head.len() - self.tail_bytes;
+        (dropped > 0).then_some((self.head.len(), dropped))
+    }
+
+    /// The kept head and tail, joined.
+    pub fn into_text(self) -> String {
+        let mut text = self.head;
+        text.extend(self.tail);
+        text
+    }
+}
+
+/// The complete output of an execution whose result was truncated.
+struct StoredOutput {
+    id: String,
+    stdout: String,
+    stderr: String,
+    value: String,
+}
+
+impl StoredOutput {
+    fn bytes(&self) -> usize {
+        self.stdout.len() + self.stderr.len() + self.value.len()
+    }
+}
+
+/// Keeps the full output of truncated executions so `read_output` can page
+/// through it.
+pub struct OutputStore {
+    next_id: AtomicU64,
+    outputs: Mutex<VecDeque<StoredOutput>>,
+}
+
+impl OutputStore {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            outputs: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Stores an execution's output and returns its id. `value` is empty for
+    /// text output.
+    pub fn store(&self, stdout: String, stderr: String, value: String) -> String {
+        let id = format!("o{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut outputs = self.outputs.lock().unwrap();
+        outputs.push_back(StoredOutput {
+            id: id.clone(),
+            stdout,
+            stderr,
+            value,
+        });
+        let mut bytes: usize = outputs.iter().map(StoredOutput::bytes).sum();
+        while bytes > STORE_BYTES && outputs.len() > 1 {
+            if let Some(dropped) = outputs.pop_front() {
+                bytes -= dropped.bytes();
+            }
+        }
+        id
+    }
+
+    /// Reads up to `length` bytes of a stored stream starting at `offset`,
+    /// moved back to the nearest character boundaries. Returns `None` for an
+    /// unknown or dropped output.
+    pub fn read(
+        &self,
+        id: &str,
+        stream: OutputStream,
+        offset: usize,
+        length: usize,
+    ) -> Option<Value> {
+        let outputs = self.outputs.lock().unwrap();
+        let output = outputs.iter().find(|output| output.id == id)?;
+        let text = match stream {
+            OutputStream::Stdout => &output.stdout,
+            OutputStream::Stderr => &output.stderr,
+            OutputStream::Value => &output.value,
+        };
+
+        let start = floor_char_boundary(text, offset.min(text.len()));
+        let mut end = floor_char_boundary(text, start.saturating_add(length).min(text.len()));
+        if end == start && start < text.len() {
+            // Always make progress, even if `length` is shorter than a
+            // character.
+            end = ceil_char_boundary(text, start + 1);
+        }
+
+        Some(json!({
+            "output_id": id,
+            "stream": stream,
+            "offset": start,
+            "next_offset": (end < text.len()).then_some(end),
+            "total_bytes": text.len(),
+            "text": &text[start..end],
+        }))
+    }
+}
@@ -0,0 +1,547 @@
+//! Allow/deny policy for the commands a script may run, checked against the
+//! commands nushell's parser finds in the script (`nu --ide-ast`).
+//!
+//! A policy file looks like:
+//!
+//! ```toml
+//! # Commands that may never run. `http` also denies `http post` etc.;
+//! # `^name` is an external binary and `^*` any external binary.
+//! deny = ["rm", "http", "^curl"]
+//!
+//! # If set, only these commands may run.
+//! allow = ["ls", "where", "get", "^git"]
+//!
+//! # If set, only these external binaries may run.
+//! externals = ["git", "rg"]
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::check::{self, ByteSpan, Position};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default)]
+    allow: Option<Vec<String>>,
+    #[serde(default)]
+    deny: Vec<String>,
+    #[serde(default)]
+    externals: Option<Vec<String>>,
+}
+
+/// Commands denied in read-only mode: they write files, send data over the
+/// network, change nu's own configuration, or run programs the policy can't
+/// see. Environment changes only last for the script's process or session,
+/// so they're not denied.
+const READ_ONLY_DENY: &[&str] = &[
+    "save",
+    "rm",
+    "mv",
+    "cp",
+    "mkdir",
+    "touch",
+    "mktemp",
+    "http post",
+    "http put",
+    "http patch",
+    "http delete",
+    "plugin add",
+    "plugin rm",
+    "config nu",
+    "config env",
+    "run-external",
+    "exec",
+    "start",
+];
+
+/// Commands that run the external binary named by their first argument.
+const EXTERNAL_RUNNERS: &[&str] = &["run-external", "exec"];
+
+/// Commands that load code from another file, which the parser output for
+/// the script doesn't include.
+const FILE_LOADERS: &[&str] = &["source", "source-env", "use", "export use", "overlay use"];
+
+/// A command the policy rejects, and where the script uses it.
+#[derive(Debug, Serialize)]
+pub struct Violation {
+    pub command: String,
+    pub external: bool,
+    /// `denied` if it's on the deny list, `not_allowed` if it's missing from
+    /// the allow list, `unverifiable` if it runs code the policy can't see.
+    pub reason: &'static str,
+    pub span: ByteSpan,
+    pub start: Position,
+    pub end: Position,
+}
+
+/// An entry of `nu --ide-ast` output.
+#[derive(Debug, Deserialize)]
+struct AstEntry {
+    shape: String,
+    content: String,
+    span: Option<AstSpan>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AstSpan {
+    start: Option<usize>,
+    end: Option<usize>,
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read policy {}: {}", path.display(), e))?;
+        toml::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Invalid policy {}: {}", path.display(), e))
+    }
+
+    /// Adds the restrictions of read-only mode: commands that mutate are
+    /// denied, and only the given external binaries may run.
+    pub fn restrict_to_read_only(&mut self, externals: &[String]) {
+        self.deny
+            .extend(READ_ONLY_DENY.iter().map(|command| command.to_string()));
+        self.externals = Some(match self.externals.take() {
+            Some(allowed) => allowed
+                .into_iter()
+                .filter(|external| externals.contains(external))
+                .collect(),
+            None => externals.to_vec(),
+        });
+    }
+
+    /// Checks every command found in the `nu --ide-ast` output for `script`,
+    /// parsed after `preamble`. The preamble declares what the script may
+    /// rely on, such as custom commands from the config, and isn't checked
+    /// itself. Returns an error if the output can't be understood, so a
+    /// parser change can't silently disable the policy.
+    ///
+    /// Code the parser output doesn't show is rejected rather than trusted:
+    /// files loaded with `source` or `use`, and, if the policy restricts
+    /// externals, programs named by an expression rather than literally.
+    pub fn violations(
+        &self,
+        preamble: &str,
+        script: &str,
+        ast: &str,
+    ) -> Result<Vec<Violation>, String> {
+        let mut entries: Vec<AstEntry> = serde_json::from_str(ast.trim())
+            .map_err(|e| format!("Unexpected parser output: {e}"))?;
+        entries.retain_mut(|entry| match &mut entry.span {
+            Some(AstSpan {
+                start: Some(start),
+                end,
+            }) => {
+                let Some(shifted) = start.checked_sub(preamble.len()) else {
+                    return false;
+                };
+                *start = shifted;
+                *end = end.map(|end| end.saturating_sub(preamble.len()));
+                true
+            }
+            _ => true,
+        });
+        let modules = declared_modules(&entries);
+
+        let mut violations = Vec::new();
+        let mut add = |command: &str, external, reason, span: &Option<AstSpan>| {
+            violations.push(violation(script, command, external, reason, span));
+        };
+        for (i, entry) in entries.iter().enumerate() {
+            match entry.shape.as_str() {
+                "shape_internalcall" => {
+                    let command = entry.content.as_str();
+                    if let Some(reason) = self.reason(command, false) {
+                        add(command, false, reason, &entry.span);
+                    }
+                    let argument = argument(&entries[i + 1..])
+                        .and_then(|argument| Some((literal(argument)?, &argument.span)));
+                    if EXTERNAL_RUNNERS.contains(&command) {
+                        match &argument {
+                            Some((binary, span)) => {
+                                if let Some(reason) = self.reason(binary, true) {
+                                    add(binary, true, reason, span);
+                                }
+                            }
+                            None if self.restricts_externals() => {
+                                add(command, false, "unverifiable", &entry.span)
+                            }
+                            None => {}
+                        }
+                    }
+                    if FILE_LOADERS.contains(&command) {
+                        // Only modules whose source the parser has seen.
+                        let seen = match command {
+                            "source" | "source-env" => false,
+                            _ => argument.is_some_and(|(name, _)| {
+                                is_std_module(&name) || modules.contains(&name)
+                            }),
+                        };
+                        if !seen {
+                            add(command, false, "unverifiable", &entry.span);
+                        }
+                    }
+                }
+                "shape_external" | "shape_external_resolved" => {
+                    let binary = unquote(entry.content.trim_start_matches('^'));
+                    if let Some(reason) = self.reason(&binary, true) {
+                        add(&binary, true, reason, &entry.span);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // `^` followed by anything but a literal name runs a program chosen
+        // at runtime, as in `^$binary`.
+        if self.restricts_externals() {
+            for (caret, _) in script.match_indices('^') {
+                if covered(&entries, caret) || in_comment(script, &entries, caret) {
+                    continue;
+                }
+                let head = entries
+                    .iter()
+                    .filter(|entry| span_start(entry).is_some_and(|start| start > caret))
+                    .min_by_key(|entry| span_start(entry));
+                let literal = head.is_some_and(|entry| {
+                    matches!(
+                        entry.shape.as_str(),
+                        "shape_external" | "shape_external_resolved"
+                    ) && span_start(entry) == Some(caret + 1)
+                });
+                if !literal {
+                    let (command, span) = match head {
+                        Some(entry) => (entry.content.as_str(), &entry.span),
+                        None => ("", &None),
+                    };
+                    let span = span.as_ref().map(|span| AstSpan {
+                        start: Some(caret),
+                        end: span.end,
+                    });
+                    add(command, true, "unverifiable", &span);
+                }
+            }
+        }
+        violations.sort_by_key(|v| v.span.start);
+        Ok(violations)
+    }
+
+    /// Whether any rule limits which external binaries may run.
+    fn restricts_externals(&self) -> bool {
+        self.externals.is_some()
+            || self.allow.is_some()
+            || self.deny.iter().any(|rule| rule.starts_with('^'))
+    }
+
+    fn reason(&self, command: &str, external: bool) -> Option<&'static str> {
+        if self
+            .deny
+            .iter()
+            .any(|rule| matches(rule, command, external))
+        {
+            return Some("denied");
+        }
+        if let Some(allow) = &self.allow {
+            if !allow.iter().any(|rule| matches(rule, command, external)) {
+                return Some("not_allowed");
+            }
+        }
+        if let (Some(externals), true) = (&self.externals, external) {
+            if !externals
+                .iter()
+                .any(|name| matches(&format!("^{}", name.trim_start_matches('^')), command, true))
+            {
+                return Some("not_allowed");
+            }
+        }
+        None
+    }
+}
+
+fn violation(
+    script: &str,
+    command: &str,
+    external: bool,
+    reason: &'static str,
+    span: &Option<AstSpan>,
+) -> Violation {
+    let (start, end) = span
+        .as_ref()
+        .map_or((None, None), |span| (span.start, span.end));
+    let start = start.unwrap_or(0).min(script.len());
+    let end = end.unwrap_or(start).clamp(start, script.len());
+    Violation {
+        command: command.to_string(),
+        external,
+        reason,
+        span: ByteSpan { start, end },
+        start: position(script, start),
+        end: position(script, end),
+    }
+}
+
+fn span_start(entry: &AstEntry) -> Option<usize> {
+    entry.span.as_ref()?.start
+}
+
+/// Whether `offset` falls inside something the parser recognized, such as a
+/// string.
+fn covered(entries: &[AstEntry], offset: usize) -> bool {
+    entries.iter().any(|entry| {
+        let span = entry.span.as_ref();
+        let start = span.and_then(|span| span.start);
+        let end = span.and_then(|span| span.end);
+        matches!((start, end), (Some(start), Some(end)) if (start..end).contains(&offset))
+    })
+}
+
+/// Whether `offset` is inside a `#` comment, which the parser output leaves
+/// out.
+fn in_comment(script: &str, entries: &[AstEntry], offset: usize) -> bool {
+    let line_start = script[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let bytes = script.as_bytes();
+    (line_start..offset).any(|i| {
+        bytes[i] == b'#'
+            && (i == line_start || bytes[i - 1].is_ascii_whitespace())
+            && !covered(entries, i)
+    })
+}
+
+/// The first argument of the call whose entries follow, skipping flags.
+fn argument(entries: &[AstEntry]) -> Option<&AstEntry> {
+    entries.iter().find(|entry| entry.shape != "shape_flag")
+}
+
+/// The value of a literal string argument, or `None` if it's computed.
+fn literal(entry: &AstEntry) -> Option<String> {
+    match entry.shape.as_str() {
+        "shape_string" | "shape_filepath" | "shape_globpattern" => Some(unquote(&entry.content)),
+        _ => None,
+    }
+}
+
+fn unquote(content: &str) -> String {
+    content.trim_matches(['\'', '"', '`']).to_string()
+}
+
+/// Modules the script defines itself, whose commands the parser output
+/// includes.
+fn declared_modules(entries: &[AstEntry]) -> Vec<String> {
+    entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| {
+            entry.shape == "shape_internalcall"
+                && matches!(entry.content.as_str(), "module" | "export module")
+        })
+        .filter_map(|(i, _)| argument(&entries[i + 1..]).and_then(literal))
+        .collect()
+}
+
+/// Whether `name` is the standard library or one of its submodules, which
+/// ship inside nu rather than being read from a file.
+fn is_std_module(name: &str) -> bool {
+    let mut parts = name.split('/');
+    parts.next() == Some("std")
+        && parts.all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// Whether `rule` covers the command. Internal rules also cover subcommands;
+/// external rules (`^name`) match the binary by name or path.
+fn matches(rule: &str, command: &str, external: bool) -> bool {
+    match (rule.strip_prefix('^'), external) {
+        (Some("*"), true) => true,
+        (Some(binary), true) => {
+            command == binary || Path::new(command).file_name() == Some(binary.as_ref())
+        }
+        (None, false) => {
+            command == rule
+                || command
+                    .strip_prefix(rule)
+                    .is_some_and(|rest| rest.starts_with(' '))
+        }
+        _ => false,
+    }
+}
+
+/// Like [`check::position`], for offsets that may not fall on a char boundary.
+fn position(script: &str, mut offset: usize) -> Position {
+    while !script.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    check::position(script, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(toml: &str) -> Policy {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn commands(policy: &Policy, preamble: &str, script: &str, ast: &str) -> Vec<String> {
+        policy
+            .violations(preamble, script, ast)
+            .unwrap()
+            .into_iter()
+            .map(|v| format!("{} {}", v.command, v.reason))
+            .collect()
+    }
+
+    #[test]
+    fn caret_external_is_checked_by_name() {
+        let script = "^curl example.com";
+        let ast = r#"[
+            {"type":"ast","span":{"start":1,"end":5},"shape":"shape_external","content":"curl"},
+            {"type":"ast","span":{"start":6,"end":17},"shape":"shape_externalarg","content":"example.com"}
+        ]"#;
+        let violations = policy(r#"deny = ["^curl"]"#)
+            .violations("", script, ast)
+            .unwrap();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].command, "curl");
+        assert!(violations[0].external);
+        assert_eq!(violations[0].reason, "denied");
+        assert_eq!((violations[0].span.start, violations[0].span.end), (1, 5));
+        assert!(commands(&policy(r#"deny = ["^wget"]"#), "", script, ast).is_empty());
+    }
+
+    #[test]
+    fn caret_before_an_expression_is_unverifiable() {
+        let script = "^$binary --version";
+        let ast = r#"[
+            {"type":"ast","span":{"start":1,"end":8},"shape":"shape_variable","content":"$binary"},
+            {"type":"ast","span":{"start":9,"end":18},"shape":"shape_externalarg","content":"--version"}
+        ]"#;
+        assert_eq!(
+            commands(&policy(r#"externals = ["git"]"#), "", script, ast),
+            ["$binary unverifiable"]
+        );
+        assert!(commands(&policy(r#"deny = ["rm"]"#), "", script, ast).is_empty());
+    }
+
+    #[test]
+    fn run_external_with_a_variable_is_unverifiable() {
+        let script = "run-external $x";
+        let ast = r#"[
+            {"type":"ast","span":{"start":0,"end":12},"shape":"shape_internalcall","content":"run-external"},
+            {"type":"ast","span":{"start":13,"end":15},"shape":"shape_variable","content":"$x"}
+        ]"#;
+        assert_eq!(
+            commands(&policy(r#"externals = ["git"]"#), "", script, ast),
+            ["run-external unverifiable"]
+        );
+        assert!(commands(&policy(r#"deny = ["rm"]"#), "", script, ast).is_empty());
+    }
+
+    #[test]
+    fn run_external_with_a_literal_is_checked_like_a_caret() {
+        let script = "run-external 'curl' example.com";
+        let ast = r#"[
+            {"type":"ast","span":{"start":0,"end":12},"shape":"shape_internalcall","content":"run-external"},
+            {"type":"ast","span":{"start":13,"end":19},"shape":"shape_string","content":"'curl'"},
+            {"type":"ast","span":{"start":20,"end":31},"shape":"shape_string","content":"example.com"}
+        ]"#;
+        assert_eq!(
+            commands(&policy(r#"externals = ["git"]"#), "", script, ast),
+            ["curl not_allowed"]
+        );
+    }
+
+    #[test]
+    fn source_of_a_variable_is_unverifiable() {
+        let script = "source $file";
+        let ast = r#"[
+            {"type":"ast","span":{"start":0,"end":6},"shape":"shape_internalcall","content":"source"},
+            {"type":"ast","span":{"start":7,"end":12},"shape":"shape_variable","content":"$file"}
+        ]"#;
+        assert_eq!(
+            commands(&policy(r#"deny = ["rm"]"#), "", script, ast),
+            ["source unverifiable"]
+        );
+    }
+
+    #[test]
+    fn use_of_std_or_a_declared_module_is_allowed() {
+        let script = "module m { export def f [] {} }; use m; use std/log; use lib.nu";
+        let ast = r#"[
+            {"type":"ast","span":{"start":0,"end":6},"shape":"shape_internalcall","content":"module"},
+            {"type":"ast","span":{"start":7,"end":8},"shape":"shape_string","content":"m"},
+            {"type":"ast","span":{"start":11,"end":21},"shape":"shape_internalcall","content":"export def"},
+            {"type":"ast","span":{"start":33,"end":36},"shape":"shape_internalcall","content":"use"},
+            {"type":"ast","span":{"start":37,"end":38},"shape":"shape_string","content":"m"},
+            {"type":"ast","span":{"start":40,"end":43},"shape":"shape_internalcall","content":"use"},
+            {"type":"ast","span":{"start":44,"end":51},"shape":"shape_string","content":"std/log"},
+            {"type":"ast","span":{"start":53,"end":56},"shape":"shape_internalcall","content":"use"},
+            {"type":"ast","span":{"start":57,"end":63},"shape":"shape_filepath","content":"lib.nu"}
+        ]"#;
+        assert_eq!(
+            commands(&policy(r#"deny = ["rm"]"#), "", script, ast),
+            ["use unverifiable"]
+        );
+    }
+
+    #[test]
+    fn internal_rule_covers_subcommands() {
+        let script = "http post https://example.com data";
+        let ast = r#"[
+            {"type":"ast","span":{"start":0,"end":9},"shape":"shape_internalcall","content":"http post"},
+            {"type":"ast","span":{"start":10,"end":29},"shape":"shape_string","content":"https://example.com"},
+            {"type":"ast","span":{"start":30,"end":34},"shape":"shape_string","content":"data"}
+        ]"#;
+        assert_eq!(
+            commands(&policy(r#"deny = ["http"]"#), "", script, ast),
+            ["http post denied"]
+        );
+        assert_eq!(
+            commands(&policy(r#"deny = ["http post"]"#), "", script, ast),
+            ["http post denied"]
+        );
+        assert!(commands(&policy(r#"deny = ["http get"]"#), "", script, ast).is_empty());
+        assert!(commands(&policy(r#"deny = ["htt"]"#), "", script, ast).is_empty());
+    }
+
+    #[test]
+    fn carets_in_strings_and_comments_are_ignored() {
+        let script = "print \"^curl\" # ^wget\nls # rm";
+        let ast = r#"[
+            {"type":"ast","span":{"start":0,"end":5},"shape":"shape_internalcall","content":"print"},
+            {"type":"ast","span":{"start":6,"end":13},"shape":"shape_string","content":"\"^curl\""},
+            {"type":"ast","span":{"start":22,"end":24},"shape":"shape_internalcall","content":"ls"}
+        ]"#;
+        assert!(commands(&policy("externals = []\ndeny = [\"rm\"]"), "", script, ast).is_empty());
+    }
+
+    #[test]
+    fn preamble_is_not_checked() {
+        let preamble = "def greet [] { ^rm x }\n";
+        let script = "greet";
+        let ast = r#"[
+            {"type":"ast","span":{"start":0,"end":3},"shape":"shape_internalcall","content":"def"},
+            {"type":"ast","span":{"start":4,"end":9},"shape":"shape_string","content":"greet"},
+            {"type":"ast","span":{"start":16,"end":18},"shape":"shape_external","content":"rm"},
+            {"type":"ast","span":{"start":19,"end":20},"shape":"shape_externalarg","content":"x"},
+            {"type":"ast","span":{"start":23,"end":28},"shape":"shape_internalcall","content":"greet"}
+        ]"#;
+        assert!(commands(&policy(r#"allow = ["greet"]"#), preamble, script, ast).is_empty());
+        let violations = policy(r#"deny = ["greet"]"#)
+            .violations(preamble, script, ast)
+            .unwrap();
+        assert_eq!((violations[0].span.start, violations[0].span.end), (0, 5));
+    }
+
+    #[test]
+    fn unexpected_output_is_an_error() {
+        assert!(policy("")
+            .violations("", "ls", "Error: nu::parser")
+            .is_err());
+    }
+}
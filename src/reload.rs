@@ -0,0 +1,162 @@
+//! Reloads the server configuration when its files change (`--watch`).
+//!
+//! The server built from the latest configuration is kept in [`Live`]. Every
+//! client serves each request from it, keeping only its own sessions, jobs and
+//! outputs, so a reload applies to connected clients right away. Clients are
+//! sent `notifications/tools/list_changed` when the tools change.
+
+use notify::{EventKind, RecursiveMode, Watcher};
+use rmcp::{Peer, RoleServer};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::tools::NuServer;
+
+/// How long to wait for more changes before reloading, so an editor's
+/// write-and-rename counts as one change.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// The current configuration, shared by every client.
+pub struct Live {
+    server: RwLock<NuServer>,
+    peers: Mutex<Vec<Peer<RoleServer>>>,
+}
+
+impl Live {
+    pub fn new(server: NuServer) -> Self {
+        Self {
+            server: RwLock::new(server),
+            peers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn server(&self) -> NuServer {
+        self.server.read().unwrap().clone()
+    }
+
+    /// Adds a client to notify when the tools change.
+    pub fn register(&self, peer: Peer<RoleServer>) {
+        let mut peers = self.peers.lock().unwrap();
+        peers.retain(|peer| !peer.is_transport_closed());
+        peers.push(peer);
+    }
+
+    async fn replace(&self, server: NuServer) {
+        let changed = tools_of(&self.server()) != tools_of(&server);
+        *self.server.write().unwrap() = server;
+        if !changed {
+            return;
+        }
+
+        info!("Tools changed, notifying clients");
+        let peers: Vec<_> = {
+            let mut peers = self.peers.lock().unwrap();
+            peers.retain(|peer| !peer.is_transport_closed());
+            peers.clone()
+        };
+        for peer in peers {
+            if let Err(e) = peer.notify_tool_list_changed().await {
+                warn!("Failed to send tools/list_changed: {}", e);
+            }
+        }
+    }
+}
+
+/// The server's tool definitions, in a comparable form.
+fn tools_of(server: &NuServer) -> Vec<Value> {
+    let mut tools = server.tools();
+    tools.sort_by(|a, b| a.name.cmp(&b.name));
+    tools
+        .into_iter()
+        .map(|tool| serde_json::to_value(tool).unwrap_or_default())
+        .collect()
+}
+
+/// Watches `files` and replaces the server in `live` with the result of
+/// `reload` whenever one of them changes. `reload` also returns the files to
+/// watch from then on. A failed reload is logged and the current
+/// configuration kept.
+pub async fn watch<F, Fut>(live: Arc<Live>, mut files: Vec<PathBuf>, reload: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = anyhow::Result<(NuServer, Vec<PathBuf>)>>,
+{
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = match notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    }) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            error!("Failed to watch configuration files: {}", e);
+            return;
+        }
+    };
+    let mut dirs = HashSet::new();
+    watch_dirs(&mut watcher, &mut dirs, &files);
+    check_nu_configs(&live.server()).await;
+
+    while let Some(event) = rx.recv().await {
+        let relevant = match event {
+            Ok(event) => {
+                !matches!(event.kind, EventKind::Access(_))
+                    && event.paths.iter().any(|path| files.contains(path))
+            }
+            Err(e) => {
+                warn!("Error watching configuration files: {}", e);
+                false
+            }
+        };
+        if !relevant {
+            continue;
+        }
+
+        tokio::time::sleep(DEBOUNCE).await;
+        while rx.try_recv().is_ok() {}
+
+        info!("Configuration changed, reloading");
+        match reload().await {
+            Ok((server, new_files)) => {
+                check_nu_configs(&server).await;
+                live.replace(server).await;
+                files = new_files;
+                watch_dirs(&mut watcher, &mut dirs, &files);
+                info!("Configuration reloaded");
+            }
+            Err(e) => error!("Failed to reload configuration, keeping the current one: {e:#}"),
+        }
+    }
+}
+
+/// Watches the directories holding `files`, rather than the files
+/// themselves, so files replaced by a rename are still seen.
+fn watch_dirs(watcher: &mut impl Watcher, dirs: &mut HashSet<PathBuf>, files: &[PathBuf]) {
+    let wanted: HashSet<PathBuf> = files
+        .iter()
+        .filter_map(|file| file.parent().map(Path::to_path_buf))
+        .collect();
+    for dir in dirs.difference(&wanted) {
+        let _ = watcher.unwatch(dir);
+    }
+    for dir in wanted.difference(dirs) {
+        if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            warn!("Failed to watch {}: {}", dir.display(), e);
+        }
+    }
+    *dirs = wanted;
+}
+
+/// Parse-checks the nu config files and logs any errors. They're read by
+/// every script, so they can't be rolled back, only reported.
+async fn check_nu_configs(server: &NuServer) {
+    for (path, errors) in server.check_nu_configs().await {
+        for error in errors {
+            error!("Invalid nu config {}: {}", path, error);
+        }
+    }
+}
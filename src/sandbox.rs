@@ -0,0 +1,153 @@
+//! OS-level restrictions for spawned nu processes: Landlock for the
+//! filesystem, a seccomp filter for network sockets, and `no_new_privs`.
+//!
+//! Rulesets and filters are built in the server before spawning, so the
+//! `pre_exec` hook only has to apply them in the child. On other systems the
+//! sandbox can't be enabled.
+
+#[cfg(target_os = "linux")]
+use landlock::{
+    path_beneath_rules, Access, AccessFs, CompatLevel, Compatible, Ruleset, RulesetAttr,
+    RulesetCreated, RulesetCreatedAttr, RulesetError, ABI,
+};
+#[cfg(target_os = "linux")]
+use seccompiler::{
+    BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+    SeccompRule,
+};
+use serde::Serialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+#[cfg(target_os = "linux")]
+/// The newest Landlock ABI whose rights are requested. Rights from later ABIs
+/// than the kernel supports are dropped; the first ABI is required.
+const ABI_USED: ABI = ABI::V3;
+
+/// Restrictions applied to the nu processes that run scripts.
+#[derive(Debug, Clone, Serialize)]
+pub struct SandboxConfig {
+    /// Paths that may be read and executed.
+    pub read: Vec<PathBuf>,
+    /// Paths that may be written.
+    pub write: Vec<PathBuf>,
+    /// Whether scripts may open network sockets. Unix sockets are always
+    /// allowed.
+    pub network: bool,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            read: vec![PathBuf::from("/")],
+            write: Vec::new(),
+            network: true,
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl SandboxConfig {
+    pub fn check(&self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The sandbox is only supported on Linux",
+        ))
+    }
+
+    pub fn apply(&self, _cmd: &mut Command, _scratch: &[&Path]) -> io::Result<()> {
+        self.check()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl SandboxConfig {
+    /// Checks that the sandbox can be enforced on this system and that the
+    /// configured paths exist.
+    pub fn check(&self) -> io::Result<()> {
+        self.ruleset(&[])
+            .map(drop)
+            .map_err(|e| io::Error::other(format!("Sandbox is not available: {e}")))?;
+        if !self.network {
+            network_filter()?;
+        }
+        Ok(())
+    }
+
+    /// Restricts `cmd` to the sandbox. The server's own `scratch` paths may
+    /// be written in addition to the configured ones.
+    pub fn apply(&self, cmd: &mut Command, scratch: &[&Path]) -> io::Result<()> {
+        let mut ruleset = Some(self.ruleset(scratch).map_err(io::Error::other)?);
+        let filter = match self.network {
+            true => None,
+            false => Some(network_filter()?),
+        };
+
+        // SAFETY: restrict_self and apply_filter only make the prctl,
+        // landlock_restrict_self and seccomp syscalls, which are safe to call
+        // between fork and exec. The hook runs in the forked child, so taking
+        // the ruleset there leaves the server's copy in place.
+        unsafe {
+            cmd.pre_exec(move || {
+                ruleset
+                    .take()
+                    .ok_or_else(|| io::Error::from_raw_os_error(libc::EPERM))?
+                    .restrict_self()
+                    .map_err(|_| io::Error::from_raw_os_error(libc::EPERM))?;
+                if let Some(filter) = &filter {
+                    seccompiler::apply_filter(filter)
+                        .map_err(|_| io::Error::from_raw_os_error(libc::EPERM))?;
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    fn ruleset(&self, scratch: &[&Path]) -> Result<RulesetCreated, RulesetError> {
+        let mut write: Vec<&Path> = self.write.iter().map(PathBuf::as_path).collect();
+        write.extend(scratch);
+        write.push(Path::new("/dev/null"));
+
+        Ruleset::default()
+            .set_compatibility(CompatLevel::HardRequirement)
+            .handle_access(AccessFs::from_all(ABI::V1))?
+            .set_compatibility(CompatLevel::BestEffort)
+            .handle_access(AccessFs::from_all(ABI_USED))?
+            .create()?
+            .add_rules(path_beneath_rules(
+                &self.read,
+                AccessFs::from_read(ABI_USED),
+            ))?
+            .add_rules(path_beneath_rules(write, AccessFs::from_all(ABI_USED)))
+    }
+}
+
+#[cfg(target_os = "linux")]
+/// A seccomp filter that fails `socket` with `EACCES` for every address
+/// family but `AF_UNIX`.
+fn network_filter() -> io::Result<BpfProgram> {
+    let build = || -> Result<BpfProgram, seccompiler::BackendError> {
+        let not_unix = SeccompCondition::new(
+            0,
+            SeccompCmpArgLen::Dword,
+            SeccompCmpOp::Ne,
+            libc::AF_UNIX as u64,
+        )?;
+        let rules = [(libc::SYS_socket, vec![SeccompRule::new(vec![not_unix])?])];
+        SeccompFilter::new(
+            rules.into_iter().collect(),
+            SeccompAction::Allow,
+            SeccompAction::Errno(libc::EACCES as u32),
+            std::env::consts::ARCH.try_into()?,
+        )?
+        .try_into()
+    };
+    build().map_err(|e| io::Error::other(format!("Failed to build network filter: {e}")))
+}
+
+/// Whether `stderr` looks like the script tripped a sandbox restriction.
+pub fn looks_blocked(stderr: &str) -> bool {
+    stderr.contains("Permission denied") || stderr.contains("Operation not permitted")
+}
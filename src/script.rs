@@ -0,0 +1,227 @@
+//! Helpers for composing nushell source text from server-side values.
+
+use serde_json::Value;
+use std::path::Path;
+
+/// Quotes `value` as a nushell raw string literal (`r#'...'#`), picking enough
+/// `#`s that the literal can't be terminated early by the value itself.
+pub fn raw_string(value: &str) -> String {
+    let mut hashes = 1;
+    while value.contains(&format!("'{}", "#".repeat(hashes))) {
+        hashes += 1;
+    }
+    let fence = "#".repeat(hashes);
+    format!("r{fence}'{value}'{fence}")
+}
+
+/// Returns true if `name` can be used as a bare nushell variable name.
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Variables nushell defines itself, which a script argument can't replace.
+const RESERVED_VARIABLES: &[&str] = &["in", "env", "nu", "it"];
+
+/// Returns true if `name` can be bound as a script argument.
+pub fn is_argument_name(name: &str) -> bool {
+    is_identifier(name) && !RESERVED_VARIABLES.contains(&name)
+}
+
+/// Returns `let` statements binding each of `names` to its value in the JSON
+/// record stored at `path`, so the values never pass through script source.
+pub fn bind_arguments<'a>(path: &Path, names: impl IntoIterator<Item = &'a String>) -> String {
+    let path = raw_string(&path.to_string_lossy());
+    names
+        .into_iter()
+        .map(|name| format!("let {name} = (open {path} | get {})\n", raw_string(name)))
+        .collect()
+}
+
+/// Renders a JSON value as a nushell expression that evaluates to the same
+/// value. Strings become raw string literals; lists and records are decoded
+/// from JSON at runtime, so no value can break out of its argument position.
+pub fn nu_value(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => raw_string(s),
+        Value::Array(_) | Value::Object(_) => {
+            format!("({} | from json)", raw_string(&value.to_string()))
+        }
+    }
+}
+
+/// Splits `source` into its top-level statements: the text between newlines
+/// and `;`s that aren't inside brackets, strings or comments. Comments are
+/// dropped. This is a lexical approximation of nushell's own rules, good
+/// enough to find declarations made at the top level.
+pub fn top_level_statements(source: &str) -> Vec<&str> {
+    let bytes = source.as_bytes();
+    let mut spans = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' | b'\'' | b'`' => i = skip_quoted(bytes, i),
+            b'r' if bytes.get(i + 1).is_some_and(|&b| b == b'#' || b == b'\'')
+                && (i == 0 || !bytes[i - 1].is_ascii_alphanumeric()) =>
+            {
+                i = skip_raw_string(source, i);
+            }
+            b'#' if i == 0
+                || bytes[i - 1].is_ascii_whitespace()
+                || b";|([{".contains(&bytes[i - 1]) =>
+            {
+                if depth == 0 {
+                    spans.push((start, i));
+                }
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                if depth == 0 {
+                    start = i;
+                }
+                continue;
+            }
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+            b'\n' | b';' if depth == 0 => {
+                spans.push((start, i));
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    spans.push((start.min(source.len()), source.len()));
+    spans
+        .into_iter()
+        .map(|(from, to)| source[from..to].trim())
+        .filter(|statement| !statement.is_empty())
+        .collect()
+}
+
+/// Returns the index of the closing quote of the string starting at `start`.
+fn skip_quoted(bytes: &[u8], start: usize) -> usize {
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() && bytes[i] != quote {
+        if quote == b'"' && bytes[i] == b'\\' {
+            i += 1;
+        }
+        i += 1;
+    }
+    i
+}
+
+/// Returns the index of the last character of the raw string (`r#'...'#`)
+/// starting at `start`.
+fn skip_raw_string(source: &str, start: usize) -> usize {
+    let hashes = source[start + 1..]
+        .bytes()
+        .take_while(|&b| b == b'#')
+        .count();
+    let open = start + 1 + hashes;
+    if source.as_bytes().get(open) != Some(&b'\'') {
+        return start;
+    }
+    let fence = format!("'{}", "#".repeat(hashes));
+    match source[open + 1..].find(&fence) {
+        Some(offset) => open + offset + fence.len(),
+        None => source.len(),
+    }
+}
+
+/// The variable a top-level `let`, `mut` or `const` statement declares, and
+/// whether it's mutable.
+pub fn declared_variable(statement: &str) -> Option<(&str, bool)> {
+    let (keyword, rest) = statement.split_once(char::is_whitespace)?;
+    let mutable = match keyword {
+        "mut" => true,
+        "let" | "const" => false,
+        _ => return None,
+    };
+    let rest = rest.trim_start();
+    let end = rest
+        .find(|c: char| c.is_whitespace() || c == ':' || c == '=')
+        .unwrap_or(rest.len());
+    let name = &rest[..end];
+    is_identifier(name).then_some((name, mutable))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statements_split_on_newlines_and_semicolons() {
+        assert_eq!(
+            top_level_statements("let a = 1; mut b = 2\n\nuse std/log\n"),
+            ["let a = 1", "mut b = 2", "use std/log"]
+        );
+    }
+
+    #[test]
+    fn brackets_keep_a_statement_together() {
+        let source = "def f [] {\n  let inner = 1; inner\n}\nlet xs = [\n  1\n  2\n]";
+        assert_eq!(
+            top_level_statements(source),
+            [
+                "def f [] {\n  let inner = 1; inner\n}",
+                "let xs = [\n  1\n  2\n]"
+            ]
+        );
+    }
+
+    #[test]
+    fn strings_hide_separators_and_brackets() {
+        let source = "let a = \"x; y # {\"\nlet b = 'it''s'; let c = `(`\nlet d = \"\\\"; }\"";
+        assert_eq!(
+            top_level_statements(source),
+            [
+                "let a = \"x; y # {\"",
+                "let b = 'it''s'",
+                "let c = `(`",
+                "let d = \"\\\"; }\""
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_strings_hide_separators_and_brackets() {
+        let source = "let a = r#'x'; {'#; let b = r'y'\nlet c = (r##'#'a'##)";
+        assert_eq!(
+            top_level_statements(source),
+            ["let a = r#'x'; {'#", "let b = r'y'", "let c = (r##'#'a'##)"]
+        );
+    }
+
+    #[test]
+    fn comments_are_dropped() {
+        let source = "# leading; comment\nlet a = 1 # trailing { comment\nlet b = a#b\ndef f [] {\n  # inside\n}";
+        assert_eq!(
+            top_level_statements(source),
+            ["let a = 1", "let b = a#b", "def f [] {\n  # inside\n}"]
+        );
+    }
+
+    #[test]
+    fn declared_variable_reads_let_mut_and_const() {
+        assert_eq!(declared_variable("let a = 1"), Some(("a", false)));
+        assert_eq!(declared_variable("mut b: int = 2"), Some(("b", true)));
+        assert_eq!(declared_variable("const c=3"), Some(("c", false)));
+        assert_eq!(declared_variable("letter = 1"), None);
+        assert_eq!(declared_variable("let $x = 1"), None);
+    }
+
+    #[test]
+    fn raw_string_outlasts_its_content() {
+        assert_eq!(raw_string("plain"), "r#'plain'#");
+        assert_eq!(raw_string("a'#b"), "r##'a'#b'##");
+    }
+}
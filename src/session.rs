@@ -0,0 +1,298 @@
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tracing::warn;
+
+use crate::script::{self, is_identifier, raw_string};
+
+/// Environment variables nushell manages itself and that must not be replayed
+/// into a new process.
+const SKIPPED_ENV: &[&str] = &[
+    "PWD",
+    "OLDPWD",
+    "FILE_PWD",
+    "CURRENT_FILE",
+    "config",
+    "ENV_CONVERSIONS",
+    "NU_VERSION",
+    "LAST_EXIT_CODE",
+    "CMD_DURATION_MS",
+    "__MCP_OUT",
+];
+
+/// State carried between `exec` calls in the same session. Every call runs in
+/// a fresh `nu` process that is primed with this state before the script runs,
+/// and that writes it back out once the script succeeds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SessionState {
+    cwd: Option<String>,
+    /// NUON-encoded record of environment variables.
+    env: Option<String>,
+    vars: Vec<SessionVar>,
+    /// Source of every custom command not imported from a module, as returned
+    /// by `view source`.
+    defs: Vec<String>,
+    /// Variables and environment variables that can't be encoded as NUON,
+    /// such as closures, and so aren't carried over.
+    not_kept: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionVar {
+    name: String,
+    /// NUON-encoded value.
+    value: String,
+}
+
+pub struct Session {
+    id: String,
+    dir: PathBuf,
+    state: SessionState,
+    /// Variables last declared with `mut`, which are restored as mutable.
+    mutable: HashSet<String>,
+    /// Top-level `use` statements, replayed so imported commands and the
+    /// private definitions they rely on stay available.
+    uses: Vec<String>,
+}
+
+impl Session {
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The working directory saved by the last successful run, if any.
+    pub fn cwd(&self) -> Option<&str> {
+        self.state.cwd.as_deref()
+    }
+
+    /// Variables and environment variables the last successful run left
+    /// that couldn't be kept for the next one.
+    pub fn not_kept(&self) -> &[String] {
+        &self.state.not_kept
+    }
+
+    pub fn script_path(&self) -> PathBuf {
+        self.dir.join("script.nu")
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.dir.join("state.json")
+    }
+
+    /// Writes `script` into the session directory and returns a wrapper script
+    /// that restores the session state, sources the script in the top-level
+    /// scope and saves the resulting state. `bindings` run right before the
+    /// script, after the state is restored. The output of `input`, if given,
+    /// is the script's `$in`. The script's final value is piped into `capture`
+    /// if given, and printed otherwise.
+    pub fn prepare(
+        &self,
+        script: &str,
+        bindings: &str,
+        input: Option<&str>,
+        capture: Option<&str>,
+    ) -> std::io::Result<String> {
+        std::fs::write(self.script_path(), script)?;
+        match std::fs::remove_file(self.state_path()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        let mut wrapper = String::new();
+        if let Some(env) = &self.state.env {
+            wrapper.push_str(&format!("load-env ({} | from nuon)\n", raw_string(env)));
+        }
+        if let Some(cwd) = &self.state.cwd {
+            wrapper.push_str(&format!("cd {}\n", raw_string(cwd)));
+        }
+        wrapper.push_str(&self.declarations());
+        wrapper.push_str(bindings);
+
+        let skipped_env = SKIPPED_ENV
+            .iter()
+            .map(|name| raw_string(name))
+            .collect::<Vec<_>>()
+            .join(" ");
+        wrapper.push_str(&format!(
+            r#"{input}source {script} | do --env {{ $env.__MCP_OUT = $in }}
+{{
+  cwd: $env.PWD
+  env: ($env
+    | items {{|name, value| {{name: $name, value: $value}} }}
+    | where name not-in [{skipped_env}]
+    | where {{|e| try {{ $e.value | to nuon | ignore; true }} catch {{ false }} }}
+    | reduce --fold {{}} {{|e, acc| $acc | insert $e.name $e.value }}
+    | to nuon)
+  vars: (scope variables
+    | where name not-in ['$nu' '$env' '$in']
+    | where {{|v| try {{ $v.value | to nuon | ignore; true }} catch {{ false }} }}
+    | each {{|v| {{name: ($v.name | str substring 1..), value: ($v.value | to nuon)}} }})
+  not_kept: (scope variables
+    | where name not-in ['$nu' '$env' '$in']
+    | where {{|v| try {{ $v.value | to nuon | ignore; false }} catch {{ true }} }}
+    | get name
+    | append ($env
+      | items {{|name, value| {{name: $name, value: $value}} }}
+      | where name not-in [{skipped_env}]
+      | where {{|e| try {{ $e.value | to nuon | ignore; false }} catch {{ true }} }}
+      | each {{|e| $"$env.($e.name)" }}))
+  defs: (scope commands
+    | where type == 'custom'
+    | where decl_id not-in (try {{ scope modules | get commands | flatten | get decl_id }} catch {{ [] }})
+    | get name
+    | each {{|name| view source $name }})
+}} | to json | save --force {state}
+$env.__MCP_OUT{capture}
+"#,
+            script = raw_string(&self.script_path().to_string_lossy()),
+            state = raw_string(&self.state_path().to_string_lossy()),
+            input = input.map(|i| format!("{i} | ")).unwrap_or_default(),
+            capture = capture.map(|c| format!(" | {c}")).unwrap_or_default(),
+        ));
+
+        Ok(wrapper)
+    }
+
+    /// The `use` statements, variables and custom commands that earlier runs
+    /// left, as statements that declare them again.
+    pub fn declarations(&self) -> String {
+        let mut declarations = String::new();
+        for statement in &self.uses {
+            declarations.push_str(statement);
+            declarations.push('\n');
+        }
+        for var in &self.state.vars {
+            declarations.push_str(&format!(
+                "{} {} = ({} | from nuon)\n",
+                match self.mutable.contains(&var.name) {
+                    true => "mut",
+                    false => "let",
+                },
+                var.name,
+                raw_string(&var.value)
+            ));
+        }
+        for def in &self.state.defs {
+            declarations.push_str(def);
+            declarations.push('\n');
+        }
+        declarations
+    }
+
+    /// Picks up the state written by a successful run of a prepared script.
+    pub fn commit(&mut self) {
+        let state = std::fs::read_to_string(self.state_path())
+            .map_err(|e| e.to_string())
+            .and_then(|s| serde_json::from_str::<SessionState>(&s).map_err(|e| e.to_string()));
+
+        match state {
+            Ok(mut state) => {
+                state.vars.retain(|var| is_identifier(&var.name));
+                self.state = state;
+                self.track_declarations();
+            }
+            Err(e) => warn!("Failed to read state for session {}: {}", self.id, e),
+        }
+    }
+
+    /// Notes the `mut` declarations and `use` statements at the top level of
+    /// the script that just succeeded.
+    fn track_declarations(&mut self) {
+        let Ok(source) = std::fs::read_to_string(self.script_path()) else {
+            return;
+        };
+        for statement in script::top_level_statements(&source) {
+            if let Some((name, mutable)) = script::declared_variable(statement) {
+                match mutable {
+                    true => self.mutable.insert(name.to_string()),
+                    false => self.mutable.remove(name),
+                };
+            } else if statement.starts_with("use ") && !self.uses.iter().any(|u| u == statement) {
+                self.uses.push(statement.to_string());
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub type SessionHandle = Arc<tokio::sync::Mutex<Session>>;
+
+/// Creates a directory that only the server's user can enter.
+pub fn private_dir() -> std::io::Result<TempDir> {
+    let mut builder = tempfile::Builder::new();
+    builder.prefix("mcp-server-nu-");
+    #[cfg(unix)]
+    builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o700));
+    builder.tempdir()
+}
+
+/// Tracks the sessions opened through `session_open`.
+pub struct SessionManager {
+    /// Holds every session's directory. Created on first use with a random
+    /// name and mode 0700, since sessions keep env values and the code the
+    /// server replays.
+    root: Mutex<Option<TempDir>>,
+    next_id: AtomicU64,
+    sessions: Mutex<HashMap<String, SessionHandle>>,
+}
+
+impl SessionManager {
+    pub fn new() -> Self {
+        Self {
+            root: Mutex::new(None),
+            next_id: AtomicU64::new(1),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn open(&self) -> std::io::Result<String> {
+        let id = format!("s{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        let dir = {
+            let mut root = self.root.lock().unwrap();
+            let root = match &mut *root {
+                Some(root) => root,
+                None => root.insert(private_dir()?),
+            };
+            root.path().join(&id)
+        };
+        // Never reuse a directory someone else created.
+        std::fs::create_dir(&dir)?;
+
+        let session = Session {
+            id: id.clone(),
+            dir,
+            state: SessionState::default(),
+            mutable: HashSet::new(),
+            uses: Vec::new(),
+        };
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(id.clone(), Arc::new(tokio::sync::Mutex::new(session)));
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<SessionHandle> {
+        self.sessions.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn close(&self, id: &str) -> bool {
+        self.sessions.lock().unwrap().remove(id).is_some()
+    }
+}
+
+impl Drop for SessionManager {
+    fn drop(&mut self) {
+        // Sessions go before their root directory is removed.
+        self.sessions.get_mut().unwrap().clear();
+    }
+}
@@ -1,26 +1,220 @@
 use rmcp::{
-    handler::server::{router::tool::ToolRouter, tool::Parameters},
+    handler::server::{
+        router::tool::{ToolRoute, ToolRouter},
+        tool::ToolCallContext,
+        wrapper::Parameters,
+    },
     model::*,
-    schemars, tool, tool_handler, tool_router, ErrorData as McpError, ServerHandler,
+    schemars,
+    service::{NotificationContext, RequestContext},
+    tool, tool_router, ErrorData as McpError, RoleServer, ServerHandler,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::future::Future;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 
+use crate::audit::{self, AuditLog, JobEvent, PolicyDecision};
+use crate::auth::{self, Grant};
+use crate::check;
+use crate::commands;
+use crate::config;
+use crate::diagnostics;
+use crate::exec::{self, Stream};
+use crate::format::{InputFormat, OutputFormat};
+use crate::jobs::{Job, JobManager, JobState};
+use crate::limits::ResourceLimits;
+use crate::module_tools::ToolsModule;
+use crate::nu_version::NuVersion;
+use crate::output::{self, OutputStore, OutputStream};
+use crate::policy::Policy;
+use crate::reload::Live;
+use crate::sandbox::{self, SandboxConfig};
+use crate::script::{self, raw_string};
+use crate::session::{self, SessionManager};
+
 #[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
 pub struct ExecRequest {
     /// The nushell script to execute.
     script: String,
-    /// Timeout in seconds for script execution (default: 30).
+    /// Timeout in seconds for script execution (default: 30, unless the
+    /// server is configured otherwise).
+    #[serde(default)]
+    timeout_seconds: Option<u64>,
+    /// Run the script in a session opened with `session_open`, keeping state
+    /// from earlier calls in that session.
+    #[serde(default)]
+    session_id: Option<String>,
+    /// How to return the script's final value (default: text). With json,
+    /// nuon or table the value is captured as data and returned in `value`
+    /// instead of being printed to stdout.
+    #[serde(default)]
+    output_format: OutputFormat,
+    /// Data passed to the script as `$in`, decoded according to
+    /// input_format. Use it for large documents instead of quoting them into
+    /// the script.
+    #[serde(default)]
+    input: Option<String>,
+    /// How to decode input (default: text). With json, nuon or csv the
+    /// script gets structured data.
+    #[serde(default)]
+    input_format: InputFormat,
+    /// Named values the script can use as variables, e.g. {"path": "a b.txt"}
+    /// makes `$path` available. Values keep their JSON types and are never
+    /// spliced into the script text, so quotes in them are harmless.
+    #[serde(default)]
+    args: JsonObject,
+    /// Directory to run the script in, absolute or relative to the server's
+    /// working directory. Can't be combined with session_id; use cd there.
+    #[serde(default)]
+    cwd: Option<String>,
+    /// Environment variables to set for the script. Can't be combined with
+    /// session_id.
+    #[serde(default)]
+    env: HashMap<String, String>,
+    /// Whether the script inherits the server's environment variables
+    /// (default: true). If false, it only gets PATH, HOME and `env`.
+    #[serde(default = "default_inherit_env")]
+    inherit_env: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct CheckRequest {
+    /// The nushell script to check.
+    script: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ListCommandsRequest {
+    /// Only list commands in this category, e.g. "filters" or "strings".
+    #[serde(default)]
+    category: Option<String>,
+    /// Only list commands of this type: "built-in", "custom", "plugin",
+    /// "keyword" or "external".
+    #[serde(default)]
+    command_type: Option<String>,
+    /// Case-insensitive text to look for in names, descriptions and search
+    /// terms.
+    #[serde(default)]
+    search: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct DescribeCommandRequest {
+    /// The full command name, e.g. "str replace".
+    name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SessionCloseRequest {
+    /// The session id returned by `session_open`.
+    session_id: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct JobStartRequest {
+    /// The nushell script to run in the background.
+    script: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct JobRequest {
+    /// The job id returned by `job_start`.
+    job_id: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct JobOutputRequest {
+    /// The job id returned by `job_start`.
+    job_id: String,
+    /// Index of the first output line to return; pass the previous
+    /// `next_offset` to tail the output (default: 0).
+    #[serde(default)]
+    offset: u64,
+    /// Maximum number of lines to return (default: 200).
+    #[serde(default = "default_job_output_limit")]
+    limit: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct ReadOutputRequest {
+    /// The output_id from the `truncated` field of an exec result.
+    output_id: String,
+    /// Which stream to read: stdout, stderr or value (default: stdout).
+    #[serde(default)]
+    stream: OutputStream,
+    /// Byte offset to start reading at; pass the previous `next_offset` to
+    /// continue (default: 0).
+    #[serde(default)]
+    offset: usize,
+    /// Maximum number of bytes to return (default: the server's output cap).
+    #[serde(default)]
+    length: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct JobWaitRequest {
+    /// The job id returned by `job_start`.
+    job_id: String,
+    /// How long to wait for the job to finish, in seconds (default: 30).
     #[serde(default = "default_timeout")]
     timeout_seconds: u64,
 }
 
 fn default_timeout() -> u64 {
-    30
+    config::DEFAULT_TIMEOUT_SECONDS
+}
+
+fn default_inherit_env() -> bool {
+    true
+}
+
+/// Variables a script keeps when it doesn't inherit the server's
+/// environment, so nu and externals can still be found.
+const BASE_ENV: &[&str] = &["PATH", "HOME"];
+
+const CHECK_TIMEOUT_SECONDS: u64 = 10;
+const QUERY_TIMEOUT_SECONDS: u64 = 10;
+
+fn default_job_output_limit() -> usize {
+    200
+}
+
+/// Forwards output lines to the client as `notifications/progress`, if the
+/// request carried a progress token. Notifications are sent in order from a
+/// single task; drop the sender and await the handle to flush them before
+/// responding.
+fn spawn_progress(
+    context: &RequestContext<RoleServer>,
+) -> Option<(mpsc::UnboundedSender<String>, JoinHandle<()>)> {
+    let progress_token = context.meta.get_progress_token()?;
+    let peer = context.peer.clone();
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+
+    let handle = tokio::spawn(async move {
+        let mut progress = 0.0;
+        while let Some(message) = rx.recv().await {
+            progress += 1.0;
+            let param = ProgressNotificationParam {
+                progress_token: progress_token.clone(),
+                progress,
+                total: None,
+                message: Some(message),
+            };
+            if let Err(e) = peer.notify_progress(param).await {
+                warn!("Failed to send progress notification: {}", e);
+            }
+        }
+    });
+
+    Some((tx, handle))
 }
 
 #[derive(Clone)]
@@ -28,6 +222,21 @@ pub struct NuServer {
     tool_router: ToolRouter<Self>,
     nu_config: Option<String>,
     nu_env_config: Option<String>,
+    sessions: Arc<SessionManager>,
+    jobs: Arc<JobManager>,
+    policy: Option<Arc<Policy>>,
+    sandbox: Option<Arc<SandboxConfig>>,
+    limits: Option<Arc<ResourceLimits>>,
+    outputs: Arc<OutputStore>,
+    max_output_bytes: usize,
+    allowed_roots: Vec<PathBuf>,
+    nu_bin: PathBuf,
+    nu_version: Option<NuVersion>,
+    env: HashMap<String, String>,
+    default_timeout: u64,
+    max_timeout: u64,
+    audit: Option<Arc<AuditLog>>,
+    live: Option<Arc<Live>>,
 }
 
 #[tool_router]
@@ -37,11 +246,260 @@ impl NuServer {
             tool_router: Self::tool_router(),
             nu_config,
             nu_env_config,
+            sessions: Arc::new(SessionManager::new()),
+            jobs: Arc::new(JobManager::new()),
+            policy: None,
+            sandbox: None,
+            limits: None,
+            outputs: Arc::new(OutputStore::new()),
+            max_output_bytes: output::DEFAULT_MAX_BYTES,
+            allowed_roots: Vec::new(),
+            nu_bin: PathBuf::from("nu"),
+            nu_version: None,
+            env: HashMap::new(),
+            default_timeout: default_timeout(),
+            max_timeout: u64::MAX,
+            audit: None,
+            live: None,
         }
     }
 
-    fn build_nu_command(&self, script: &str) -> Command {
-        let mut cmd = Command::new("nu");
+    /// Returns a server for another client: the same tools and nushell
+    /// configuration, with its own sessions, jobs and stored outputs.
+    pub fn new_client(&self) -> Self {
+        Self {
+            tool_router: self.tool_router.clone(),
+            nu_config: self.nu_config.clone(),
+            nu_env_config: self.nu_env_config.clone(),
+            sessions: Arc::new(SessionManager::new()),
+            jobs: Arc::new(JobManager::new()),
+            policy: self.policy.clone(),
+            sandbox: self.sandbox.clone(),
+            limits: self.limits.clone(),
+            outputs: Arc::new(OutputStore::new()),
+            max_output_bytes: self.max_output_bytes,
+            allowed_roots: self.allowed_roots.clone(),
+            nu_bin: self.nu_bin.clone(),
+            nu_version: self.nu_version,
+            env: self.env.clone(),
+            default_timeout: self.default_timeout,
+            max_timeout: self.max_timeout,
+            audit: self.audit.clone(),
+            live: self.live.clone(),
+        }
+    }
+
+    /// Checks scripts from `exec` and `job_start` against `policy` before
+    /// running them.
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = Some(Arc::new(policy));
+    }
+
+    /// Runs scripts inside `sandbox`.
+    pub fn set_sandbox(&mut self, sandbox: SandboxConfig) -> std::io::Result<()> {
+        sandbox.check()?;
+        self.sandbox = Some(Arc::new(sandbox));
+        Ok(())
+    }
+
+    /// Blocks scripts from writing files or sending data over the network:
+    /// mutating commands and externals other than `externals` are rejected
+    /// before running, and the sandbox makes the filesystem read-only. The
+    /// network stays reachable for reads, so only the policy stops writes
+    /// there.
+    pub fn set_read_only(&mut self, externals: &[String]) -> std::io::Result<()> {
+        let mut sandbox = self.sandbox.as_deref().cloned().unwrap_or_default();
+        sandbox.write.clear();
+        self.set_sandbox(sandbox)?;
+
+        let policy = self.policy.get_or_insert_with(Default::default);
+        Arc::make_mut(policy).restrict_to_read_only(externals);
+        Ok(())
+    }
+
+    /// Applies `limits` to every process that runs a script.
+    pub fn set_limits(&mut self, limits: ResourceLimits) {
+        self.limits = Some(Arc::new(limits));
+    }
+
+    /// Runs scripts with the nu binary at `nu_bin`, or found on PATH.
+    pub fn set_nu_bin(&mut self, nu_bin: PathBuf) {
+        self.nu_bin = nu_bin;
+    }
+
+    /// Records the version of the nu binary, as found by
+    /// [`crate::nu_version::probe`], for `get_info` and `server_status`.
+    pub fn set_nu_version(&mut self, version: NuVersion) {
+        self.nu_version = Some(version);
+    }
+
+    /// Sets `env` for every nu process the server starts.
+    pub fn set_env(&mut self, env: HashMap<String, String>) {
+        self.env = env;
+    }
+
+    /// Sets the timeout of `exec` calls that don't pass one, and the longest
+    /// one they may pass.
+    pub fn set_timeouts(&mut self, default_seconds: u64, max_seconds: u64) {
+        self.default_timeout = default_seconds;
+        self.max_timeout = max_seconds;
+    }
+
+    /// Records every script run by `exec`, `job_start` and module tools in
+    /// `audit`.
+    pub fn set_audit_log(&mut self, audit: AuditLog) {
+        self.audit = Some(Arc::new(audit));
+    }
+
+    pub fn audit_log(&self) -> Option<Arc<AuditLog>> {
+        self.audit.clone()
+    }
+
+    /// Serves requests with the configuration in `live`, which is replaced
+    /// when the configuration is reloaded.
+    pub fn set_live(&mut self, live: Arc<Live>) {
+        self.live = Some(live);
+    }
+
+    /// This client's server with the latest configuration.
+    fn current(&self) -> NuServer {
+        let Some(live) = &self.live else {
+            return self.clone();
+        };
+        NuServer {
+            sessions: self.sessions.clone(),
+            jobs: self.jobs.clone(),
+            outputs: self.outputs.clone(),
+            live: self.live.clone(),
+            ..live.server()
+        }
+    }
+
+    pub fn tools(&self) -> Vec<Tool> {
+        self.tool_router.list_all()
+    }
+
+    /// Parse-checks the nu config files, returning the errors found in each.
+    pub async fn check_nu_configs(&self) -> Vec<(String, Vec<String>)> {
+        let mut invalid = Vec::new();
+        for path in [&self.nu_config, &self.nu_env_config].into_iter().flatten() {
+            let source = match std::fs::read_to_string(path) {
+                Ok(source) => source,
+                Err(e) => {
+                    invalid.push((path.clone(), vec![e.to_string()]));
+                    continue;
+                }
+            };
+            let mut cmd = Command::new(&self.nu_bin);
+            cmd.env_remove(auth::TOKENS_ENV_VAR)
+                .arg("--no-config-file")
+                .arg("--ide-check")
+                .arg("100")
+                .arg(path)
+                .stdin(std::process::Stdio::null())
+                .kill_on_drop(true);
+            let output = match tokio::time::timeout(
+                Duration::from_secs(CHECK_TIMEOUT_SECONDS),
+                cmd.output(),
+            )
+            .await
+            {
+                Ok(Ok(output)) => output,
+                Ok(Err(e)) => {
+                    invalid.push((path.clone(), vec![format!("Parse check failed: {e}")]));
+                    continue;
+                }
+                Err(_) => {
+                    invalid.push((path.clone(), vec!["Parse check timed out".to_string()]));
+                    continue;
+                }
+            };
+
+            let errors: Vec<_> =
+                check::parse_ide_check(&source, &String::from_utf8_lossy(&output.stdout))
+                    .into_iter()
+                    .filter(|d| d.severity == "Error")
+                    .map(|d| format!("line {}: {}", d.start.line, d.message))
+                    .collect();
+            if !errors.is_empty() {
+                invalid.push((path.clone(), errors));
+            }
+        }
+        invalid
+    }
+
+    /// Caps the stdout and stderr of `exec` results at `max_bytes` each.
+    pub fn set_max_output_bytes(&mut self, max_bytes: usize) {
+        self.max_output_bytes = max_bytes;
+    }
+
+    /// Only lets exec requests set a `cwd` inside one of `roots`.
+    pub fn set_allowed_roots(&mut self, roots: &[PathBuf]) -> std::io::Result<()> {
+        self.allowed_roots = roots
+            .iter()
+            .map(|root| {
+                root.canonicalize().map_err(|e| {
+                    std::io::Error::new(
+                        e.kind(),
+                        format!("Invalid allowed root {}: {e}", root.display()),
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(())
+    }
+
+    /// Resolves the `cwd` of an exec request, following symlinks, and checks
+    /// that it's a directory inside an allowed root.
+    fn resolve_cwd(&self, cwd: &str) -> Result<PathBuf, McpError> {
+        let resolved = Path::new(cwd)
+            .canonicalize()
+            .map_err(|e| McpError::invalid_params(format!("Invalid cwd {cwd}: {e}"), None))?;
+        if !resolved.is_dir() {
+            return Err(McpError::invalid_params(
+                format!("Invalid cwd {cwd}: not a directory"),
+                None,
+            ));
+        }
+        if !self.allowed_roots.is_empty()
+            && !self
+                .allowed_roots
+                .iter()
+                .any(|root| resolved.starts_with(root))
+        {
+            warn!("Rejected cwd outside allowed roots: {}", resolved.display());
+            return Err(McpError::invalid_params(
+                format!("cwd {cwd} is outside the allowed roots"),
+                Some(json!({ "allowed_roots": self.allowed_roots })),
+            ));
+        }
+        Ok(resolved)
+    }
+
+    /// Applies the sandbox and resource limits, if any, to `cmd`. `scratch`
+    /// are the server's own files for this run, which may be written in
+    /// addition to the sandbox's write roots.
+    fn restrict(&self, cmd: &mut Command, scratch: &[&Path]) -> Result<(), McpError> {
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.apply(cmd, scratch).map_err(|e| {
+                error!("Failed to sandbox nu: {}", e);
+                McpError::internal_error(format!("Failed to sandbox nu: {e}"), None)
+            })?;
+        }
+        if let Some(limits) = &self.limits {
+            limits.apply(cmd).map_err(|e| {
+                error!("Failed to apply resource limits: {}", e);
+                McpError::internal_error(format!("Failed to apply resource limits: {e}"), None)
+            })?;
+        }
+        Ok(())
+    }
+
+    fn nu_command(&self) -> Command {
+        let mut cmd = Command::new(&self.nu_bin);
+        // Scripts mustn't be able to read the tokens that guard the server.
+        cmd.env_remove(auth::TOKENS_ENV_VAR);
+        cmd.envs(&self.env);
 
         if let Some(config_path) = &self.nu_config {
             cmd.arg("--config").arg(config_path);
@@ -51,11 +509,235 @@ impl NuServer {
             cmd.arg("--env-config").arg(env_config_path);
         }
 
-        cmd.arg("-c").arg(script).stdin(std::process::Stdio::null());
+        cmd.stdin(std::process::Stdio::null());
 
         cmd
     }
 
+    fn build_nu_command(&self, script: &str) -> Command {
+        let mut cmd = self.nu_command();
+        cmd.arg("-c").arg(script);
+        cmd
+    }
+
+    /// Sets the working directory and environment variables `cmd` runs with.
+    fn apply_environment(
+        &self,
+        cmd: &mut Command,
+        cwd: Option<&Path>,
+        env: &HashMap<String, String>,
+        inherit_env: bool,
+    ) -> Result<(), McpError> {
+        if let Some((name, _)) = env.iter().find(|(name, value)| {
+            name.is_empty() || name.contains(['=', '\0']) || value.contains('\0')
+        }) {
+            return Err(McpError::invalid_params(
+                format!("Invalid environment variable: {name:?}"),
+                None,
+            ));
+        }
+
+        if !inherit_env {
+            cmd.env_clear();
+            for name in BASE_ENV {
+                if let Some(value) = std::env::var_os(name) {
+                    cmd.env(name, value);
+                }
+            }
+            cmd.envs(&self.env);
+        }
+        cmd.envs(env);
+        if let Some(cwd) = cwd {
+            cmd.current_dir(cwd);
+        }
+        Ok(())
+    }
+
+    /// Runs a script of the server's own that prints JSON, and parses it.
+    async fn query_nu(&self, script: &str, cancel: &CancellationToken) -> Result<Value, McpError> {
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        let completion = exec::run(
+            self.build_nu_command(script),
+            Some(Duration::from_secs(QUERY_TIMEOUT_SECONDS)),
+            cancel,
+            |stream, line| match stream {
+                Stream::Stdout => stdout.push_str(line),
+                Stream::Stderr => stderr.push_str(line),
+            },
+        )
+        .await
+        .map_err(|e| {
+            error!("Command execution failed: {}", e);
+            McpError::internal_error(format!("Command execution failed: {e}"), None)
+        })?;
+
+        if completion.exit_code != 0 {
+            error!("nu query failed: {}", stderr);
+            return Err(McpError::internal_error(
+                "nu query failed",
+                Some(json!({ "exit_code": completion.exit_code, "stderr": stderr })),
+            ));
+        }
+
+        serde_json::from_str(&stdout).map_err(|e| {
+            error!("Failed to parse nu query output: {}", e);
+            McpError::internal_error(format!("Failed to parse nu query output: {e}"), None)
+        })
+    }
+
+    /// Starts the audit log entry for a script run by `tool`.
+    fn audit_entry(
+        &self,
+        tool: &str,
+        script: &str,
+        context: &RequestContext<RoleServer>,
+    ) -> audit::Entry {
+        let mut entry = audit::Entry::new(tool, script);
+        entry.client = context.peer.peer_info().map(|info| json!(info.client_info));
+        entry
+    }
+
+    /// Records in `entry` whether the policy check let the script run.
+    fn audit_policy(&self, entry: &mut audit::Entry, checked: &Result<(), McpError>) {
+        entry.policy = match (&self.policy, checked) {
+            (None, _) => PolicyDecision::Unchecked,
+            (Some(_), Ok(())) => PolicyDecision::Allowed,
+            (Some(_), Err(e)) => {
+                entry.violations = e
+                    .data
+                    .as_ref()
+                    .and_then(|data| data.get("violations"))
+                    .cloned();
+                match entry.violations {
+                    Some(_) => PolicyDecision::Denied,
+                    None => PolicyDecision::Error,
+                }
+            }
+        };
+    }
+
+    /// Writes `entry`, completed with the outcome of the call, to the audit
+    /// log if there is one.
+    fn audit(&self, mut entry: audit::Entry, result: &Result<CallToolResult, McpError>) {
+        let Some(log) = &self.audit else {
+            return;
+        };
+        if let Err(e) = result {
+            entry.error = Some(e.message.to_string());
+        }
+        log.record(entry);
+    }
+
+    /// Records how the job started with `started` ends, once it does.
+    fn audit_job_end(&self, job: &Arc<Job>, started: &audit::Entry) {
+        let Some(log) = self.audit.clone() else {
+            return;
+        };
+        let job = job.clone();
+        let started = started.clone();
+        tokio::spawn(async move {
+            let state = job.finished().await;
+            let mut entry = started.job_finished();
+            match state {
+                JobState::Exited { exit_code } => entry.exit_code = Some(exit_code),
+                JobState::Killed => entry.cancelled = true,
+                JobState::Failed { error } => entry.error = Some(error),
+                JobState::Running => {}
+            }
+            entry.set_duration(job.elapsed());
+            let (stdout_bytes, stderr_bytes) = job.output_bytes();
+            entry.stdout_bytes = Some(stdout_bytes);
+            entry.stderr_bytes = Some(stderr_bytes);
+            log.record(entry);
+        });
+    }
+
+    /// Rejects the script if it uses a command the policy doesn't permit.
+    /// The script is parsed after the config and, if given, the session's
+    /// declarations, so the custom commands it would run with are known.
+    async fn enforce_policy(
+        &self,
+        script: &str,
+        session_id: Option<&str>,
+        cancel: &CancellationToken,
+    ) -> Result<(), McpError> {
+        let Some(policy) = &self.policy else {
+            return Ok(());
+        };
+
+        // `--ide-ast` doesn't load the config, so declare its commands by sourcing it.
+        let mut preamble = String::new();
+        for path in [&self.nu_env_config, &self.nu_config].into_iter().flatten() {
+            preamble.push_str(&format!("source {}\n", raw_string(path)));
+        }
+        if let Some(session) = session_id.and_then(|id| self.sessions.get(id)) {
+            preamble.push_str(&session.lock().await.declarations());
+        }
+
+        let file = tempfile::Builder::new()
+            .suffix(".nu")
+            .tempfile()
+            .and_then(|mut file| {
+                std::io::Write::write_all(&mut file, preamble.as_bytes())?;
+                std::io::Write::write_all(&mut file, script.as_bytes())?;
+                Ok(file)
+            })
+            .map_err(|e| {
+                error!("Failed to write script for policy check: {}", e);
+                McpError::internal_error(
+                    format!("Failed to write script for policy check: {e}"),
+                    None,
+                )
+            })?;
+
+        let mut cmd = self.nu_command();
+        cmd.arg("--ide-ast").arg(file.path());
+        let mut ast = String::new();
+        let completion = exec::run(
+            cmd,
+            Some(Duration::from_secs(CHECK_TIMEOUT_SECONDS)),
+            cancel,
+            |stream, line| {
+                if let Stream::Stdout = stream {
+                    ast.push_str(line);
+                }
+            },
+        )
+        .await
+        .map_err(|e| {
+            error!("Policy check failed: {}", e);
+            McpError::internal_error(format!("Policy check failed: {e}"), None)
+        })?;
+        if completion.cancelled {
+            return Err(McpError::internal_error("Request cancelled", None));
+        }
+
+        let violations = policy.violations(&preamble, script, &ast).map_err(|e| {
+            error!("Policy check failed: {}", e);
+            McpError::internal_error(format!("Policy check failed: {e}"), None)
+        })?;
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        let commands: Vec<_> = violations
+            .iter()
+            .map(|v| match v.external {
+                true => format!("^{}", v.command),
+                false => v.command.clone(),
+            })
+            .collect();
+        warn!("Rejected script using {}", commands.join(", "));
+        Err(McpError::invalid_params(
+            format!(
+                "Script uses commands not permitted by policy: {}",
+                commands.join(", ")
+            ),
+            Some(json!({ "violations": violations })),
+        ))
+    }
+
     #[tool(
         description = r"Executes a nushell script and returns stdout, stderr, and exit code.
 
@@ -68,6 +750,9 @@ Execute ONE command at a time that produces meaningful output. For complex tasks
 AVOID: Long multi-step scripts that could fail midway
 PREFER: Single commands with clear output, then assess and continue
 
+SESSIONS:
+Each call runs in a fresh nu process. To keep 'cd', $env changes, 'let' bindings and 'def' commands between calls, open a session with session_open and pass its session_id.
+
 IMPORTANT NUSHELL SYNTAX DIFFERENCES FROM POSIX:
 
 Line Continuation:
@@ -90,73 +775,807 @@ Data Types:
 - Nushell is structured data focused
 - Commands return tables/records, not just text
 - Use 'to csv' to convert structured data for LLM consumption
+- Or set output_format to 'json' to get the final value back as data
 - Use 'to text' for plain text output"
     )]
     async fn exec(
         &self,
         Parameters(req): Parameters<ExecRequest>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let mut audit = self.audit_entry("exec", &req.script, &context);
+        let checked = self
+            .enforce_policy(&req.script, req.session_id.as_deref(), &context.ct)
+            .await;
+        self.audit_policy(&mut audit, &checked);
+        let result = match checked {
+            Ok(()) => self.run_exec(req, context, &mut audit).await,
+            Err(e) => Err(e),
+        };
+        self.audit(audit, &result);
+        result
+    }
+
+    /// Runs an exec request without checking it against the command policy,
+    /// filling in the details of the run in `audit`.
+    async fn run_exec(
+        &self,
+        req: ExecRequest,
+        context: RequestContext<RoleServer>,
+        audit: &mut audit::Entry,
     ) -> Result<CallToolResult, McpError> {
         info!(
             "Executing nushell script: {}",
             req.script.chars().take(100).collect::<String>()
         );
+        audit.session_id = req.session_id.clone();
+        audit.cwd = req.cwd.as_ref().map(PathBuf::from);
+        audit.env = req.env.clone();
+        audit.args = req.args.clone();
+        audit.input = req.input.clone();
+        audit.inherit_env = req.inherit_env;
+        audit.timeout_seconds = Some(req.timeout_seconds.unwrap_or(self.default_timeout));
 
-        let timeout_duration = Duration::from_secs(req.timeout_seconds);
-        let command_future = self.build_nu_command(&req.script).output();
+        let session = req
+            .session_id
+            .as_deref()
+            .map(|id| {
+                self.sessions
+                    .get(id)
+                    .ok_or_else(|| McpError::invalid_params(format!("Unknown session: {id}"), None))
+            })
+            .transpose()?;
+        if session.is_some() && (req.cwd.is_some() || !req.env.is_empty() || !req.inherit_env) {
+            return Err(McpError::invalid_params(
+                "cwd, env and inherit_env can't be combined with session_id; use cd and $env in the session instead",
+                None,
+            ));
+        }
+        let cwd = req
+            .cwd
+            .as_deref()
+            .map(|cwd| self.resolve_cwd(cwd))
+            .transpose()?;
+        let mut session = match &session {
+            Some(session) => Some(session.lock().await),
+            None => None,
+        };
 
-        let output = match tokio::time::timeout(timeout_duration, command_future).await {
-            Ok(result) => result.map_err(|e| {
-                error!("Command execution failed: {}", e);
-                McpError::internal_error(format!("Command execution failed: {e}"), None)
-            })?,
-            Err(_) => {
-                warn!(
-                    "Command timed out after {} seconds",
-                    timeout_duration.as_secs()
-                );
-                return Err(McpError::internal_error(
-                    format!("Command timed out after {} seconds. Consider breaking down complex scripts into smaller steps.",
-                    timeout_duration.as_secs()),
-                    None
-                ));
+        if let (Some(input), InputFormat::Json) = (&req.input, req.input_format) {
+            serde_json::from_str::<Value>(input).map_err(|e| {
+                McpError::invalid_params(format!("Input is not valid JSON: {e}"), None)
+            })?;
+        }
+
+        let timeout_seconds = req.timeout_seconds.unwrap_or(self.default_timeout);
+        if timeout_seconds > self.max_timeout {
+            return Err(McpError::invalid_params(
+                format!(
+                    "timeout_seconds ({timeout_seconds}) exceeds the server's maximum of {}",
+                    self.max_timeout
+                ),
+                None,
+            ));
+        }
+
+        if let Some(name) = req.args.keys().find(|name| !script::is_argument_name(name)) {
+            return Err(McpError::invalid_params(
+                format!("Invalid argument name: {name:?}"),
+                None,
+            ));
+        }
+
+        let scratch = match (req.output_format, &req.input) {
+            (OutputFormat::Text, None) if req.args.is_empty() => None,
+            _ => Some(session::private_dir().map_err(|e| {
+                error!("Failed to create scratch directory: {}", e);
+                McpError::internal_error(format!("Failed to create scratch directory: {e}"), None)
+            })?),
+        };
+        let value_path = scratch
+            .as_ref()
+            .filter(|_| req.output_format != OutputFormat::Text)
+            .map(|dir| dir.path().join("value"));
+        let capture = value_path
+            .as_deref()
+            .and_then(|path| req.output_format.capture(path));
+        let input = match (&req.input, &scratch) {
+            (Some(input), Some(scratch)) => {
+                let path = scratch.path().join(req.input_format.file_name());
+                std::fs::write(&path, input).map_err(|e| {
+                    error!("Failed to write script input: {}", e);
+                    McpError::internal_error(format!("Failed to write script input: {e}"), None)
+                })?;
+                Some(req.input_format.reader(&path))
             }
+            _ => None,
         };
 
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        let exit_code = output.status.code().unwrap_or(-1);
+        let bindings = match &scratch {
+            Some(scratch) if !req.args.is_empty() => {
+                let path = scratch.path().join("args.json");
+                std::fs::write(&path, serde_json::to_string(&req.args).unwrap()).map_err(|e| {
+                    error!("Failed to write script arguments: {}", e);
+                    McpError::internal_error(format!("Failed to write script arguments: {e}"), None)
+                })?;
+                script::bind_arguments(&path, req.args.keys())
+            }
+            _ => String::new(),
+        };
+
+        // The file the script is sourced from, when it runs inside a wrapper.
+        let script_file = match (&session, &scratch) {
+            (Some(session), _) => Some(session.script_path()),
+            (None, Some(scratch)) => Some(scratch.path().join("script.nu")),
+            _ => None,
+        };
+        let script = match (&session, &script_file) {
+            (Some(session), _) => {
+                session.prepare(&req.script, &bindings, input.as_deref(), capture.as_deref())
+            }
+            (None, Some(script_file)) => std::fs::write(script_file, &req.script).map(|_| {
+                format!(
+                    "{bindings}{}source {}{}\n",
+                    input.map(|i| format!("{i} | ")).unwrap_or_default(),
+                    raw_string(&script_file.to_string_lossy()),
+                    capture.map(|c| format!(" | {c}")).unwrap_or_default(),
+                )
+            }),
+            _ => Ok(req.script.clone()),
+        }
+        .map_err(|e| {
+            error!("Failed to prepare script: {}", e);
+            McpError::internal_error(format!("Failed to prepare script: {e}"), None)
+        })?;
+
+        // Never keep less than a result may show.
+        let capture_bytes = self.max_output_bytes.max(output::CAPTURE_BYTES);
+        let mut stdout = output::Capture::new(capture_bytes);
+        let mut stderr = output::Capture::new(capture_bytes);
+        let progress = spawn_progress(&context);
+        let on_line = |stream: Stream, line: &str| {
+            match stream {
+                Stream::Stdout => stdout.push(line),
+                Stream::Stderr => stderr.push(line),
+            }
+            if let Some((tx, _)) = &progress {
+                let line = line.trim_end_matches(['\r', '\n']);
+                let message = match stream {
+                    Stream::Stdout => line.to_string(),
+                    Stream::Stderr => format!("[stderr] {line}"),
+                };
+                let _ = tx.send(message);
+            }
+        };
+
+        let timeout_duration = Duration::from_secs(timeout_seconds);
+        let mut cmd = self.build_nu_command(&script);
+        self.apply_environment(&mut cmd, cwd.as_deref(), &req.env, req.inherit_env)?;
+        let writable: Vec<&Path> = [
+            session.as_ref().map(|session| session.dir()),
+            scratch.as_ref().map(|scratch| scratch.path()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        self.restrict(&mut cmd, &writable)?;
+        let completion = exec::run(cmd, Some(timeout_duration), &context.ct, on_line)
+            .await
+            .map_err(|e| {
+                error!("Command execution failed: {}", e);
+                McpError::internal_error(format!("Command execution failed: {e}"), None)
+            })?;
+
+        if let Some((tx, handle)) = progress {
+            drop(tx);
+            let _ = handle.await;
+        }
+
+        let (stdout_bytes, stdout_dropped) = (stdout.total_bytes(), stdout.dropped());
+        let (stderr_bytes, stderr_dropped) = (stderr.total_bytes(), stderr.dropped());
+        let (stdout, stderr) = (stdout.into_text(), stderr.into_text());
 
-        if exit_code != 0 {
+        let limit_exceeded = self
+            .limits
+            .as_ref()
+            .and_then(|limits| limits.exceeded(&completion, &stderr));
+        let exec::Completion {
+            exit_code,
+            cancelled,
+            timed_out,
+            elapsed,
+            ..
+        } = completion;
+        audit.set_duration(elapsed);
+        audit.exit_code = Some(exit_code);
+        audit.timed_out = timed_out;
+        audit.cancelled = cancelled;
+        audit.stdout_bytes = Some(stdout_bytes);
+        audit.stderr_bytes = Some(stderr_bytes);
+
+        if cancelled {
+            warn!(
+                "Request {} cancelled, killed nu process. Partial stdout: {:?}, stderr: {:?}",
+                context.id, stdout, stderr
+            );
+            return Err(McpError::internal_error(
+                "Request cancelled",
+                Some(json!({ "stdout": stdout, "stderr": stderr })),
+            ));
+        }
+
+        if timed_out {
+            warn!(
+                "Command timed out after {} seconds",
+                timeout_duration.as_secs()
+            );
+        } else if exit_code != 0 {
             warn!(
                 "Command exited with non-zero code: {}, stderr: {}",
                 exit_code, stderr
             );
         } else {
             info!("Command completed successfully");
+            if let Some(session) = &mut session {
+                session.commit();
+            }
+        }
+
+        let mut result = json!({
+            "stdout": &stdout,
+            "stderr": &stderr,
+            "exit_code": exit_code,
+            "timed_out": timed_out,
+            "elapsed_ms": elapsed.as_millis() as u64
+        });
+        let effective_cwd = match &session {
+            Some(session) => session.cwd().map(PathBuf::from),
+            None => cwd.or_else(|| std::env::current_dir().ok()),
+        };
+        if let Some(cwd) = effective_cwd {
+            result["cwd"] = json!(cwd);
+            audit.cwd = Some(cwd);
+        }
+        if let Some(session) = session.as_ref().filter(|_| !timed_out && exit_code == 0) {
+            if !session.not_kept().is_empty() {
+                result["session"] = json!({
+                    "not_kept": session.not_kept(),
+                    "message": "These values can't be encoded as NUON, e.g. closures, so they won't exist in the next call of this session. Recreate them in the script that uses them.",
+                });
+            }
+        }
+        if timed_out {
+            result["message"] = json!(format!(
+                "Command timed out after {} seconds and was killed; stdout and stderr hold what it printed before that. Consider breaking down complex scripts into smaller steps.",
+                timeout_duration.as_secs()
+            ));
+        }
+
+        if let (Some(exceeded), Some(limits)) = (limit_exceeded, &self.limits) {
+            let limit = exceeded.limit;
+            let cause = match exceeded.certain {
+                true => format!("The script was stopped by the {limit} resource limit."),
+                false => format!("The script was killed, possibly by the {limit} resource limit."),
+            };
+            result["limit_exceeded"] = json!({
+                "limit": limit,
+                "value": json!(limits)[limit],
+                "certain": exceeded.certain,
+                "message": format!("{cause} Reduce the work it does in one call, e.g. by filtering earlier or processing less data."),
+            });
+        }
+        if let Some(sandbox) = &self.sandbox {
+            if exit_code != 0 && sandbox::looks_blocked(&stderr) {
+                result["sandbox"] = json!({
+                    "message": "The script was denied an operation, most likely by the sandbox. It may only read and write the paths listed here, and only open network sockets if network is true.",
+                    "restrictions": sandbox,
+                });
+            }
+        }
+
+        let mut errors = diagnostics::parse_errors(&stderr);
+        if !errors.is_empty() {
+            diagnostics::attribute_to_script(&mut errors, script_file.as_deref());
+            result["errors"] = json!(errors);
+        }
+
+        let value = value_path.map(|path| {
+            match std::fs::metadata(&path) {
+                // Too large to read into memory, let alone return.
+                Ok(metadata) if metadata.len() > capture_bytes as u64 => Err(metadata.len()),
+                _ => Ok(std::fs::read_to_string(path)
+                    .map(|raw| req.output_format.decode(raw))
+                    .unwrap_or(Value::Null)),
+            }
+        });
+        let value_text = match &value {
+            Some(Ok(value)) => Some(serde_json::to_string(value).unwrap())
+                .filter(|text| text.len() > self.max_output_bytes),
+            _ => None,
+        };
+
+        let stdout_cut = output::truncate(&stdout, self.max_output_bytes);
+        let stderr_cut = output::truncate(&stderr, self.max_output_bytes);
+        let value_cut = matches!(value, Some(Err(_))) || value_text.is_some();
+        if stdout_cut.is_some()
+            || stderr_cut.is_some()
+            || stdout_dropped.is_some()
+            || stderr_dropped.is_some()
+            || value_cut
+        {
+            let mut truncated = json!({
+                "message": "Output exceeded the size limit and was cut in the middle. Page through the full output with read_output, or narrow the script's output, e.g. with 'first' or 'where'.",
+            });
+            for (stream, cut, total_bytes, dropped) in [
+                ("stdout", stdout_cut, stdout_bytes, stdout_dropped),
+                ("stderr", stderr_cut, stderr_bytes, stderr_dropped),
+            ] {
+                if cut.is_none() && dropped.is_none() {
+                    continue;
+                }
+                let omitted_bytes = cut.as_ref().map_or(0, |(_, omitted)| *omitted)
+                    + dropped.map_or(0, |(_, bytes)| bytes);
+                if let Some((shown, _)) = cut {
+                    result[stream] = json!(shown);
+                }
+                truncated[stream] = json!({
+                    "total_bytes": total_bytes,
+                    "omitted_bytes": omitted_bytes,
+                });
+                if let Some((offset, bytes)) = dropped {
+                    // Not even read_output has these.
+                    truncated[stream]["dropped"] = json!({ "offset": offset, "bytes": bytes });
+                }
+            }
+            match &value {
+                Some(Err(total_bytes)) => {
+                    truncated["value"] = json!({ "total_bytes": total_bytes, "stored": false });
+                }
+                _ if value_cut => {
+                    truncated["value"] = json!({
+                        "total_bytes": value_text.as_ref().map_or(0, String::len),
+                        "stored": true,
+                    });
+                }
+                _ => {}
+            }
+            let output_id = self
+                .outputs
+                .store(stdout, stderr, value_text.unwrap_or_default());
+            info!("Stored truncated output as {}", output_id);
+            truncated["output_id"] = json!(output_id);
+            result["truncated"] = truncated;
         }
 
+        let Some(value) = value else {
+            return Ok(CallToolResult::success(vec![Content::text(
+                serde_json::to_string_pretty(&result).unwrap(),
+            )]));
+        };
+
+        // A value too large to return is only available from read_output.
+        result["value"] = match (value, value_cut) {
+            (Ok(value), false) => value,
+            _ => Value::Null,
+        };
+
+        Ok(CallToolResult {
+            content: vec![Content::text(
+                serde_json::to_string_pretty(&result).unwrap(),
+            )],
+            structured_content: Some(result),
+            is_error: Some(false),
+            meta: None,
+        })
+    }
+
+    #[tool(
+        description = "Parse-checks a nushell script without running it. Returns 'valid' and a list of diagnostics with message, byte span, line/column start and end, the offending text and, for common POSIX habits, a help hint. Use before exec to catch syntax errors cheaply."
+    )]
+    async fn check(
+        &self,
+        Parameters(req): Parameters<CheckRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let file = tempfile::Builder::new()
+            .suffix(".nu")
+            .tempfile()
+            .and_then(|mut file| {
+                std::io::Write::write_all(&mut file, req.script.as_bytes())?;
+                Ok(file)
+            })
+            .map_err(|e| {
+                error!("Failed to write script for checking: {}", e);
+                McpError::internal_error(format!("Failed to write script for checking: {e}"), None)
+            })?;
+
+        let mut cmd = self.nu_command();
+        cmd.arg("--ide-check")
+            .arg("100")
+            .arg(file.path())
+            .kill_on_drop(true);
+        let output = tokio::time::timeout(Duration::from_secs(CHECK_TIMEOUT_SECONDS), cmd.output())
+            .await
+            .map_err(|_| McpError::internal_error("Parse check timed out", None))?
+            .map_err(|e| {
+                error!("Parse check failed: {}", e);
+                McpError::internal_error(format!("Parse check failed: {e}"), None)
+            })?;
+
+        let diagnostics =
+            check::parse_ide_check(&req.script, &String::from_utf8_lossy(&output.stdout));
+        let valid = !diagnostics.iter().any(|d| d.severity == "Error");
+
         let result = json!({
-            "stdout": stdout,
-            "stderr": stderr,
-            "exit_code": exit_code
+            "valid": valid,
+            "diagnostics": diagnostics,
         });
+        Ok(CallToolResult::success(vec![Content::text(
+            serde_json::to_string_pretty(&result).unwrap(),
+        )]))
+    }
+
+    #[tool(
+        description = "Lists the nushell commands available to exec, including custom commands from the server's nu config. Filter by category, command_type (e.g. 'plugin') or a search string. Returns name, category, type and description; use describe_command for details."
+    )]
+    async fn list_commands(
+        &self,
+        Parameters(req): Parameters<ListCommandsRequest>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let commands = self
+            .query_nu(commands::LIST_COMMANDS_SCRIPT, &context.ct)
+            .await?;
+        let commands: Vec<commands::CommandSummary> =
+            serde_json::from_value(commands).map_err(|e| {
+                error!("Unexpected scope commands output: {}", e);
+                McpError::internal_error(format!("Unexpected scope commands output: {e}"), None)
+            })?;
+        let commands = commands::filter_commands(
+            commands,
+            req.category.as_deref(),
+            req.command_type.as_deref(),
+            req.search.as_deref(),
+        );
+
+        let result = json!({
+            "count": commands.len(),
+            "commands": commands,
+        });
+        Ok(CallToolResult::success(vec![Content::text(
+            serde_json::to_string_pretty(&result).unwrap(),
+        )]))
+    }
+
+    #[tool(
+        description = "Describes a nushell command: its signatures with input/output types, positional parameters and flags, and usage examples. Use it instead of guessing at flags."
+    )]
+    async fn describe_command(
+        &self,
+        Parameters(req): Parameters<DescribeCommandRequest>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let mut command = self
+            .query_nu(&commands::describe_command_script(&req.name), &context.ct)
+            .await?;
+        if command.is_null() {
+            return Err(McpError::invalid_params(
+                format!("Unknown command: {}", req.name),
+                None,
+            ));
+        }
+        commands::reshape_signatures(&mut command);
+
+        Ok(CallToolResult::success(vec![Content::text(
+            serde_json::to_string_pretty(&command).unwrap(),
+        )]))
+    }
+
+    #[tool(
+        description = "Opens a persistent session. Pass the returned session_id to exec to keep the working directory, environment variables, variables and custom commands from one call to the next. Only successful calls update the session."
+    )]
+    async fn session_open(&self) -> Result<CallToolResult, McpError> {
+        let session_id = self.sessions.open().map_err(|e| {
+            error!("Failed to open session: {}", e);
+            McpError::internal_error(format!("Failed to open session: {e}"), None)
+        })?;
+        info!("Opened session {}", session_id);
+
+        let result = json!({ "session_id": session_id });
+        Ok(CallToolResult::success(vec![Content::text(
+            serde_json::to_string_pretty(&result).unwrap(),
+        )]))
+    }
+
+    #[tool(description = "Closes a session opened with session_open and discards its state.")]
+    async fn session_close(
+        &self,
+        Parameters(req): Parameters<SessionCloseRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        if !self.sessions.close(&req.session_id) {
+            return Err(McpError::invalid_params(
+                format!("Unknown session: {}", req.session_id),
+                None,
+            ));
+        }
+        info!("Closed session {}", req.session_id);
+
+        let result = json!({ "session_id": req.session_id, "closed": true });
+        Ok(CallToolResult::success(vec![Content::text(
+            serde_json::to_string_pretty(&result).unwrap(),
+        )]))
+    }
+
+    #[tool(
+        description = "Reads the full stdout, stderr or JSON-encoded value of an exec call whose output was truncated, by byte offset and length. Pass the returned next_offset on the next call to continue; it is null once the end is reached."
+    )]
+    async fn read_output(
+        &self,
+        Parameters(req): Parameters<ReadOutputRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let length = req.length.unwrap_or(self.max_output_bytes);
+        let page = self
+            .outputs
+            .read(&req.output_id, req.stream, req.offset, length)
+            .ok_or_else(|| {
+                McpError::invalid_params(format!("Unknown output: {}", req.output_id), None)
+            })?;
 
+        Ok(CallToolResult::success(vec![Content::text(
+            serde_json::to_string_pretty(&page).unwrap(),
+        )]))
+    }
+
+    #[tool(
+        description = "Reports the server's version, the nu binary it runs and that binary's version, and the timeout and output limits that apply to exec."
+    )]
+    async fn server_status(&self) -> Result<CallToolResult, McpError> {
+        let result = json!({
+            "server_version": env!("CARGO_PKG_VERSION"),
+            "nu": {
+                "bin": self.nu_bin,
+                "version": self.nu_version,
+                "supported_versions": NuVersion::supported_range(),
+            },
+            "default_timeout_seconds": self.default_timeout,
+            "max_timeout_seconds": (self.max_timeout != u64::MAX).then_some(self.max_timeout),
+            "max_output_bytes": self.max_output_bytes,
+        });
         Ok(CallToolResult::success(vec![Content::text(
             serde_json::to_string_pretty(&result).unwrap(),
         )]))
     }
+
+    fn get_job(&self, job_id: &str) -> Result<Arc<Job>, McpError> {
+        self.jobs
+            .get(job_id)
+            .ok_or_else(|| McpError::invalid_params(format!("Unknown job: {job_id}"), None))
+    }
+
+    #[tool(
+        description = "Starts a nushell script in the background and returns a job_id right away. Use for dev servers, watchers and long test runs that don't fit in exec's timeout. Follow up with job_status, job_output, job_wait and job_kill."
+    )]
+    async fn job_start(
+        &self,
+        Parameters(req): Parameters<JobStartRequest>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let mut audit = self.audit_entry("job_start", &req.script, &context);
+        audit.event = Some(JobEvent::Started);
+        let checked = self.enforce_policy(&req.script, None, &context.ct).await;
+        self.audit_policy(&mut audit, &checked);
+        let result = checked.and_then(|()| {
+            info!(
+                "Starting background job: {}",
+                req.script.chars().take(100).collect::<String>()
+            );
+
+            let mut cmd = self.build_nu_command(&req.script);
+            self.restrict(&mut cmd, &[])?;
+            let job = self.jobs.start(cmd, &req.script);
+            audit.job_id = Some(job.id().to_string());
+            audit.cwd = std::env::current_dir().ok();
+            self.audit_job_end(&job, &audit);
+
+            Ok(CallToolResult::success(vec![Content::text(
+                serde_json::to_string_pretty(&job.status()).unwrap(),
+            )]))
+        });
+        self.audit(audit, &result);
+        result
+    }
+
+    #[tool(description = "Returns the state, exit code and elapsed time of a background job.")]
+    async fn job_status(
+        &self,
+        Parameters(req): Parameters<JobRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let job = self.get_job(&req.job_id)?;
+
+        Ok(CallToolResult::success(vec![Content::text(
+            serde_json::to_string_pretty(&job.status()).unwrap(),
+        )]))
+    }
+
+    #[tool(
+        description = "Reads output lines of a background job starting at offset. Pass the returned next_offset on the next call to tail the output. Only the most recent output is buffered; 'dropped' counts requested lines that are no longer available."
+    )]
+    async fn job_output(
+        &self,
+        Parameters(req): Parameters<JobOutputRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let job = self.get_job(&req.job_id)?;
+
+        Ok(CallToolResult::success(vec![Content::text(
+            serde_json::to_string_pretty(&job.read(req.offset, req.limit)).unwrap(),
+        )]))
+    }
+
+    #[tool(
+        description = "Waits up to timeout_seconds for a background job to finish and returns its status."
+    )]
+    async fn job_wait(
+        &self,
+        Parameters(req): Parameters<JobWaitRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let job = self.get_job(&req.job_id)?;
+        job.wait(Duration::from_secs(req.timeout_seconds)).await;
+
+        Ok(CallToolResult::success(vec![Content::text(
+            serde_json::to_string_pretty(&job.status()).unwrap(),
+        )]))
+    }
+
+    #[tool(description = "Kills a background job and its child processes.")]
+    async fn job_kill(
+        &self,
+        Parameters(req): Parameters<JobRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let job = self.get_job(&req.job_id)?;
+        job.kill().await;
+        info!("Killed job {}", req.job_id);
+
+        Ok(CallToolResult::success(vec![Content::text(
+            serde_json::to_string_pretty(&job.status()).unwrap(),
+        )]))
+    }
+}
+
+impl NuServer {
+    /// Registers the commands exported by `module` as tools. Each call runs
+    /// the command through `exec`, so it streams, times out and reports
+    /// errors like any other script.
+    pub async fn load_tools_module(&mut self, module: ToolsModule) -> anyhow::Result<()> {
+        let introspected = self
+            .query_nu(&module.introspect_script(), &CancellationToken::new())
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to load tools module: {}", e.message))?;
+        let commands = module
+            .commands(introspected)
+            .map_err(|e| anyhow::anyhow!("Failed to load tools module: {e}"))?;
+        let module = Arc::new(module);
+
+        for command in commands {
+            if self.tool_router.has_route(&command.tool_name) {
+                warn!(
+                    "Skipping module command '{}': tool '{}' already exists",
+                    command.command, command.tool_name
+                );
+                continue;
+            }
+            info!(
+                "Registering module command '{}' as tool '{}'",
+                command.command, command.tool_name
+            );
+
+            let tool = Tool::new(
+                command.tool_name.clone(),
+                command.description.clone(),
+                Arc::new(command.input_schema()),
+            );
+            let module = module.clone();
+            let command = Arc::new(command);
+            self.tool_router.add_route(ToolRoute::new_dyn(
+                tool,
+                move |context: ToolCallContext<'_, Self>| {
+                    let module = module.clone();
+                    let command = command.clone();
+                    Box::pin(async move {
+                        let arguments = context.arguments.unwrap_or_default();
+                        let script = module
+                            .invocation(&command, &arguments)
+                            .map_err(|e| McpError::invalid_params(e, None))?;
+                        let req = ExecRequest {
+                            script,
+                            timeout_seconds: None,
+                            session_id: None,
+                            output_format: OutputFormat::default(),
+                            input: None,
+                            input_format: InputFormat::default(),
+                            args: JsonObject::new(),
+                            cwd: None,
+                            env: HashMap::new(),
+                            inherit_env: default_inherit_env(),
+                        };
+                        // Module commands are vetted by whoever configured
+                        // the server, so they aren't subject to the policy.
+                        let server = context.service;
+                        let mut audit = server.audit_entry(
+                            &command.tool_name,
+                            &req.script,
+                            &context.request_context,
+                        );
+                        audit.policy = PolicyDecision::Exempt;
+                        let result = server
+                            .run_exec(req, context.request_context, &mut audit)
+                            .await;
+                        server.audit(audit, &result);
+                        result
+                    })
+                },
+            ));
+        }
+        Ok(())
+    }
 }
 
-#[tool_handler]
 impl ServerHandler for NuServer {
     fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder()
+        let capabilities = match self.live {
+            Some(_) => ServerCapabilities::builder()
                 .enable_tools()
+                .enable_tool_list_changed()
                 .build(),
+            None => ServerCapabilities::builder().enable_tools().build(),
+        };
+        let mut instructions = "This server executes Nushell scripts and returns their output. It provides structured data processing capabilities with proper error handling and timeouts.".to_string();
+        if let Some(version) = self.current().nu_version {
+            instructions.push_str(&format!(" Scripts run on Nushell {version}."));
+        }
+        ServerInfo {
+            protocol_version: ProtocolVersion::V_2024_11_05,
+            capabilities,
             server_info: Implementation::from_build_env(),
-            instructions: Some("This server executes Nushell scripts and returns their output. It provides structured data processing capabilities with proper error handling and timeouts.".to_string()),
+            instructions: Some(instructions),
+        }
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, McpError> {
+        let grant = Grant::of(&context);
+        let tools = self
+            .current()
+            .tools()
+            .into_iter()
+            .filter(|tool| grant.is_none_or(|grant| grant.allows(&tool.name)))
+            .collect();
+        Ok(ListToolsResult::with_all_items(tools))
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if let Some(grant) = Grant::of(&context) {
+            if !grant.allows(&request.name) {
+                return Err(McpError::new(
+                    auth::FORBIDDEN,
+                    format!("Token is not permitted to call tool '{}'", request.name),
+                    None,
+                ));
+            }
+        }
+        let server = self.current();
+        let context = ToolCallContext::new(&server, request, context);
+        server.tool_router.call(context).await
+    }
+
+    async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+        if let Some(live) = &self.live {
+            live.register(context.peer);
         }
     }
 }
@@ -0,0 +1,111 @@
+//! Serves MCP over a Unix domain socket, one client per connection.
+
+use anyhow::{Context, Result};
+use rmcp::ServiceExt;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
+
+use crate::tools::NuServer;
+
+/// Only the user running the server may connect.
+const SOCKET_MODE: u32 = 0o600;
+
+/// Removes the socket file when the listener goes away.
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+pub async fn serve(server: NuServer, path: &Path) -> Result<()> {
+    remove_stale_socket(path).await?;
+
+    let listener = bind_private(path)?;
+    let _socket_file = SocketFile(path.to_path_buf());
+    info!("Serving MCP on unix socket {}", path.display());
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        };
+
+        // Each connection gets its own server, sharing the tools and config
+        // but not nushell sessions or jobs.
+        let server = server.new_client();
+        tokio::spawn(async move {
+            match server.serve(stream).await {
+                Ok(service) => {
+                    if let Err(e) = service.waiting().await {
+                        warn!("Connection closed with error: {}", e);
+                    }
+                }
+                Err(e) => warn!("Failed to initialize connection: {}", e),
+            }
+        });
+    }
+
+    info!("Shutting down unix socket listener");
+    Ok(())
+}
+
+/// Binds a socket at `path` that only the current user can connect to from
+/// the moment it appears there. The socket is created inside a private
+/// directory, tightened, and then linked into place. Unlike narrowing the
+/// umask, this doesn't affect files other threads create meanwhile.
+fn bind_private(path: &Path) -> Result<UnixListener> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    // Mode 0700, so nobody else can reach the socket inside.
+    let private = tempfile::Builder::new()
+        .prefix(".mcp-nu-")
+        .permissions(fs::Permissions::from_mode(0o700))
+        .tempdir_in(parent)
+        .with_context(|| format!("Failed to create a directory in {}", parent.display()))?;
+    let staged = private.path().join("socket");
+    let listener = UnixListener::bind(&staged)
+        .with_context(|| format!("Failed to bind {}", path.display()))?;
+    fs::set_permissions(&staged, fs::Permissions::from_mode(SOCKET_MODE))?;
+    // Unlike a rename, linking fails rather than replacing a file that
+    // appeared at `path` since the stale socket check.
+    fs::hard_link(&staged, path).with_context(|| format!("Failed to bind {}", path.display()))?;
+    Ok(listener)
+}
+
+/// Removes a socket left behind by a server that didn't shut down cleanly.
+/// Refuses to touch anything that isn't a socket, or a socket that another
+/// server is still accepting connections on.
+async fn remove_stale_socket(path: &Path) -> Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if !metadata.file_type().is_socket() {
+        anyhow::bail!("{} exists and is not a socket", path.display());
+    }
+    if UnixStream::connect(path).await.is_ok() {
+        anyhow::bail!("{} is in use by another server", path.display());
+    }
+
+    info!("Removing stale socket {}", path.display());
+    fs::remove_file(path)?;
+    Ok(())
+}
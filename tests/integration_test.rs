@@ -5,7 +5,7 @@ use std::process::{Child, Command, Stdio};
 
 struct McpTestHarness {
     child: Child,
-    stdin: std::process::ChildStdin,
+    stdin: Option<std::process::ChildStdin>,
     stdout_reader: BufReader<std::process::ChildStdout>,
     next_id: u64,
 }
@@ -41,7 +41,7 @@ impl McpTestHarness {
         }
 
         let mut child = cmd.spawn()?;
-        let stdin = child.stdin.take().unwrap();
+        let stdin = child.stdin.take();
         let stdout = child.stdout.take().unwrap();
         let stdout_reader = BufReader::new(stdout);
 
@@ -68,8 +68,9 @@ impl McpTestHarness {
             "params": params
         });
 
-        writeln!(self.stdin, "{}", serde_json::to_string(&request)?)?;
-        self.stdin.flush()?;
+        let stdin = self.stdin.as_mut().expect("stdin is open");
+        writeln!(stdin, "{}", serde_json::to_string(&request)?)?;
+        stdin.flush()?;
         Ok(id)
     }
 
@@ -84,8 +85,9 @@ impl McpTestHarness {
             "params": params
         });
 
-        writeln!(self.stdin, "{}", serde_json::to_string(&request)?)?;
-        self.stdin.flush()?;
+        let stdin = self.stdin.as_mut().expect("stdin is open");
+        writeln!(stdin, "{}", serde_json::to_string(&request)?)?;
+        stdin.flush()?;
         Ok(())
     }
 
@@ -134,6 +136,26 @@ impl McpTestHarness {
         )
     }
 
+    /// Calls the tool `name` and parses the JSON in the text content of its
+    /// successful result.
+    fn call_tool_json(
+        &mut self,
+        name: &str,
+        arguments: Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let id = self.send_tool_call(name, arguments)?;
+        let response = self.assert_response_success(id)?;
+        let text = response["result"]["content"][0]["text"].as_str().unwrap();
+        Ok(serde_json::from_str(text)?)
+    }
+
+    /// Closes stdin, as a client disconnecting does, and waits for the
+    /// server to exit.
+    fn close(&mut self) -> Result<std::process::ExitStatus, Box<dyn std::error::Error>> {
+        self.stdin.take();
+        Ok(self.child.wait()?)
+    }
+
     fn assert_response_success(
         &mut self,
         expected_id: u64,
@@ -228,17 +250,24 @@ fn test_server_continues_after_timeout() -> Result<(), Box<dyn std::error::Error
     // Step 2: Send initialized notification
     harness.send_initialized_notification()?;
 
-    // Step 3: Call exec with a sleep command that will timeout (sleep 1s with 1s timeout)
+    // Step 3: Call exec with a script that prints, then sleeps past its 1s timeout
     let exec_id = harness.send_tool_call(
         "exec",
         json!({
-            "script": "sleep 1sec",
+            "script": "print started; sleep 5sec",
             "timeout_seconds": 1
         }),
     )?;
 
-    // Step 4: Assert we get a timeout error response immediately
-    harness.assert_response_error(exec_id, "timed out")?;
+    // Step 4: Assert we get a timed out result carrying the partial output
+    let exec_response = harness.assert_response_success(exec_id)?;
+    let result_text = exec_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let result_json: Value = serde_json::from_str(result_text)?;
+    assert_eq!(result_json["timed_out"], true);
+    assert_eq!(result_json["stdout"], "started\n");
+    assert!(result_json["elapsed_ms"].as_u64().unwrap() < 5000);
 
     // Step 5: Send 3 "ping" requests (tools/list) to verify server is still responsive
     for i in 1..=3 {
@@ -472,3 +501,1631 @@ fn test_missing_config_file_behavior() -> Result<(), Box<dyn std::error::Error>>
 
     Ok(())
 }
+
+#[test]
+fn test_session_keeps_state_between_calls() -> Result<(), Box<dyn std::error::Error>> {
+    let mut harness = McpTestHarness::new()?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let open_id = harness.send_tool_call("session_open", json!({}))?;
+    let open_response = harness.assert_response_success(open_id)?;
+    let open_text = open_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let session_id = serde_json::from_str::<Value>(open_text)?["session_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let setup_script = r#"
+cd /tmp
+$env.SESSION_VAR = "kept"
+let greeting = "hello"
+def shout [s: string] { $s | str upcase }
+"#;
+    let setup_id = harness.send_tool_call(
+        "exec",
+        json!({
+            "script": setup_script,
+            "timeout_seconds": 10,
+            "session_id": session_id
+        }),
+    )?;
+    harness.assert_response_success(setup_id)?;
+
+    let exec_id = harness.send_tool_call(
+        "exec",
+        json!({
+            "script": r#"print $"(pwd) ($env.SESSION_VAR) (shout $greeting)""#,
+            "timeout_seconds": 10,
+            "session_id": session_id
+        }),
+    )?;
+    let exec_response = harness.assert_response_success(exec_id)?;
+    let result_text = exec_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let result_json: Value = serde_json::from_str(result_text)?;
+    assert_eq!(result_json["stdout"], "/tmp kept HELLO\n");
+
+    let close_id = harness.send_tool_call("session_close", json!({ "session_id": session_id }))?;
+    harness.assert_response_success(close_id)?;
+
+    let closed_id = harness.send_tool_call(
+        "exec",
+        json!({
+            "script": "pwd",
+            "session_id": session_id
+        }),
+    )?;
+    harness.assert_response_error(closed_id, "Unknown session")?;
+
+    Ok(())
+}
+
+#[test]
+fn test_session_keeps_mutable_and_imported_state() -> Result<(), Box<dyn std::error::Error>> {
+    let mut harness = McpTestHarness::new()?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let session = harness.call_tool_json("session_open", json!({}))?;
+    let session_id = session["session_id"].as_str().unwrap();
+
+    let result = harness.call_tool_json(
+        "exec",
+        json!({
+            "script": "use std log\nmut count = 0\nlet add_one = {|x| $x + 1 }",
+            "session_id": session_id,
+        }),
+    )?;
+    assert_eq!(result["exit_code"], 0, "result: {result}");
+    assert_eq!(result["session"]["not_kept"], json!(["$add_one"]));
+
+    let result = harness.call_tool_json(
+        "exec",
+        json!({
+            "script": "$count += 1; log debug 'still imported'; $count",
+            "session_id": session_id,
+        }),
+    )?;
+    assert_eq!(result["exit_code"], 0, "result: {result}");
+    assert_eq!(result["stdout"].as_str().unwrap().trim(), "1");
+    assert!(result["session"].is_null());
+
+    Ok(())
+}
+
+#[test]
+fn test_exec_streams_progress_notifications() -> Result<(), Box<dyn std::error::Error>> {
+    let mut harness = McpTestHarness::new()?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let exec_id = harness.send_request(
+        "tools/call",
+        json!({
+            "name": "exec",
+            "arguments": {
+                "script": "print one; print --stderr two; print three",
+                "timeout_seconds": 10
+            },
+            "_meta": { "progressToken": "exec-progress" }
+        }),
+    )?;
+
+    let mut messages = Vec::new();
+    let response = loop {
+        let message = harness.read_response()?;
+        if message["method"] == "notifications/progress" {
+            assert_eq!(message["params"]["progressToken"], "exec-progress");
+            messages.push(message["params"]["message"].as_str().unwrap().to_string());
+        } else {
+            break message;
+        }
+    };
+
+    assert_eq!(response["id"], exec_id);
+    // stdout and stderr are separate pipes, so only the order within each
+    // stream is guaranteed.
+    let stdout_messages: Vec<_> = messages
+        .iter()
+        .filter(|m| !m.starts_with("[stderr]"))
+        .collect();
+    assert_eq!(stdout_messages, vec!["one", "three"]);
+    assert!(messages.contains(&"[stderr] two".to_string()));
+
+    let result_text = response["result"]["content"][0]["text"].as_str().unwrap();
+    let result_json: Value = serde_json::from_str(result_text)?;
+    assert_eq!(result_json["stdout"], "one\nthree\n");
+    assert_eq!(result_json["stderr"], "two\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_cancelled_exec_kills_process() -> Result<(), Box<dyn std::error::Error>> {
+    let mut harness = McpTestHarness::new()?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let started = std::time::Instant::now();
+    let exec_id = harness.send_tool_call(
+        "exec",
+        json!({
+            "script": "sleep 20sec",
+            "timeout_seconds": 30
+        }),
+    )?;
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    harness.send_notification(
+        "notifications/cancelled",
+        json!({ "requestId": exec_id, "reason": "test" }),
+    )?;
+
+    harness.assert_response_error(exec_id, "cancelled")?;
+    assert!(
+        started.elapsed() < std::time::Duration::from_secs(10),
+        "Cancelled script should be killed instead of running to completion"
+    );
+
+    let ping_id = harness.send_tools_list()?;
+    harness.assert_response_success(ping_id)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_timeout_returns_while_detached_process_holds_output(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut harness = McpTestHarness::new()?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    // setsid moves sleep out of the process group, so the kill misses it
+    // and it keeps stdout open.
+    let started = std::time::Instant::now();
+    let result = harness.call_tool_json(
+        "exec",
+        json!({
+            "script": "^setsid sleep 30",
+            "timeout_seconds": 1
+        }),
+    )?;
+    assert_eq!(result["timed_out"], true);
+    assert!(
+        started.elapsed() < std::time::Duration::from_secs(10),
+        "Timed out script should return without waiting for the detached process"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_background_job_lifecycle() -> Result<(), Box<dyn std::error::Error>> {
+    let mut harness = McpTestHarness::new()?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let start_id = harness.send_tool_call(
+        "job_start",
+        json!({ "script": "1..3 | each {|i| print $i }; sleep 30sec" }),
+    )?;
+    let start_response = harness.assert_response_success(start_id)?;
+    let start_text = start_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let job_id = serde_json::from_str::<Value>(start_text)?["job_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    std::thread::sleep(std::time::Duration::from_secs(2));
+
+    let output_id =
+        harness.send_tool_call("job_output", json!({ "job_id": job_id, "offset": 1 }))?;
+    let output_response = harness.assert_response_success(output_id)?;
+    let output_text = output_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let output_json: Value = serde_json::from_str(output_text)?;
+    assert_eq!(output_json["next_offset"], 3);
+    assert_eq!(output_json["lines"][0]["text"], "2\n");
+    assert_eq!(output_json["lines"][1]["text"], "3\n");
+
+    let status_id = harness.send_tool_call("job_status", json!({ "job_id": job_id }))?;
+    let status_response = harness.assert_response_success(status_id)?;
+    let status_text = status_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    assert_eq!(
+        serde_json::from_str::<Value>(status_text)?["state"],
+        "running"
+    );
+
+    let kill_id = harness.send_tool_call("job_kill", json!({ "job_id": job_id }))?;
+    let kill_response = harness.assert_response_success(kill_id)?;
+    let kill_text = kill_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    assert_eq!(serde_json::from_str::<Value>(kill_text)?["state"], "killed");
+
+    Ok(())
+}
+
+#[test]
+fn test_exec_returns_structured_value() -> Result<(), Box<dyn std::error::Error>> {
+    let mut harness = McpTestHarness::new()?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let exec_id = harness.send_tool_call(
+        "exec",
+        json!({
+            "script": "print 'side effect'; [[name size]; [a 1] [b 2]]",
+            "timeout_seconds": 10,
+            "output_format": "json"
+        }),
+    )?;
+    let exec_response = harness.assert_response_success(exec_id)?;
+    let structured = &exec_response["result"]["structuredContent"];
+
+    assert_eq!(
+        structured["value"],
+        json!([{ "name": "a", "size": 1 }, { "name": "b", "size": 2 }])
+    );
+    assert_eq!(structured["stdout"], "side effect\n");
+    assert_eq!(structured["exit_code"], 0);
+
+    // The text content carries the same result for clients without
+    // structuredContent support.
+    let result_text = exec_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    assert_eq!(&serde_json::from_str::<Value>(result_text)?, structured);
+
+    Ok(())
+}
+
+#[test]
+fn test_jobs_are_killed_when_server_exits() -> Result<(), Box<dyn std::error::Error>> {
+    let mut harness = McpTestHarness::new()?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let started = harness.call_tool_json(
+        "job_start",
+        json!({ "script": "print $nu.pid; sleep 30sec" }),
+    )?;
+    let job_id = started["job_id"].as_str().unwrap().to_string();
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    let output = harness.call_tool_json("job_output", json!({ "job_id": job_id }))?;
+    let pid = output["lines"][0]["text"]
+        .as_str()
+        .unwrap()
+        .trim()
+        .to_string();
+    let proc = std::path::Path::new("/proc").join(&pid);
+    assert!(proc.exists(), "Job should be running");
+
+    harness.close()?;
+    for _ in 0..50 {
+        if !proc.exists() {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    panic!("Job {pid} outlived the server");
+}
+
+#[test]
+fn test_check_reports_parse_errors_without_running() -> Result<(), Box<dyn std::error::Error>> {
+    let mut harness = McpTestHarness::new()?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let marker = std::env::temp_dir().join("mcp-server-nu-check-marker");
+    let _ = std::fs::remove_file(&marker);
+
+    let valid_id = harness.send_tool_call(
+        "check",
+        json!({ "script": format!("'ran' | save {}", marker.display()) }),
+    )?;
+    let valid_response = harness.assert_response_success(valid_id)?;
+    let valid_text = valid_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let valid_json: Value = serde_json::from_str(valid_text)?;
+    assert_eq!(valid_json["valid"], true);
+    assert!(!marker.exists(), "check must not run the script");
+
+    let invalid_id =
+        harness.send_tool_call("check", json!({ "script": "ls\nprint a && print b" }))?;
+    let invalid_response = harness.assert_response_success(invalid_id)?;
+    let invalid_text = invalid_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let invalid_json: Value = serde_json::from_str(invalid_text)?;
+    assert_eq!(invalid_json["valid"], false);
+
+    let diagnostic = &invalid_json["diagnostics"][0];
+    assert_eq!(diagnostic["start"]["line"], 2);
+    assert_eq!(diagnostic["text"], "&&");
+    assert!(diagnostic["help"].as_str().unwrap().contains("and"));
+
+    Ok(())
+}
+
+#[test]
+fn test_exec_returns_structured_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let mut harness = McpTestHarness::new()?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let exec_id = harness.send_tool_call(
+        "exec",
+        json!({
+            "script": "let x = 1\n[[name]; [a]] | get nme",
+            "timeout_seconds": 10
+        }),
+    )?;
+    let exec_response = harness.assert_response_success(exec_id)?;
+    let result_text = exec_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let result_json: Value = serde_json::from_str(result_text)?;
+
+    assert_ne!(result_json["exit_code"], 0);
+    assert!(!result_json["stderr"].as_str().unwrap().is_empty());
+
+    let error = &result_json["errors"][0];
+    assert!(error["code"].as_str().unwrap().starts_with("nu::"));
+    assert!(!error["message"].as_str().unwrap().is_empty());
+
+    let label = error["labels"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|label| label["text"].as_str().unwrap().contains("nme"))
+        .expect("label pointing at the missing column");
+    assert_eq!(label["source"], "script");
+    assert_eq!(label["line"], 2);
+    assert_eq!(label["column"], 21);
+    assert_eq!(label["end_column"], 23);
+
+    Ok(())
+}
+
+#[test]
+fn test_command_discovery_tools() -> Result<(), Box<dyn std::error::Error>> {
+    let fixtures_path = std::env::current_dir()?.join("tests/fixtures");
+    let config_path = fixtures_path.join("nushell/config.nu");
+
+    let mut harness = McpTestHarness::new_with_options(
+        None,
+        Some(vec!["--nu-config", config_path.to_str().unwrap()]),
+    )?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    // Custom commands from --nu-config are listed
+    let list_id = harness.send_tool_call(
+        "list_commands",
+        json!({ "command_type": "custom", "search": "test_custom" }),
+    )?;
+    let list_response = harness.assert_response_success(list_id)?;
+    let list_text = list_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let list_json: Value = serde_json::from_str(list_text)?;
+    let names: Vec<_> = list_json["commands"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["test_custom_cmd"]);
+
+    // Category filtering
+    let filters_id = harness.send_tool_call("list_commands", json!({ "category": "filters" }))?;
+    let filters_response = harness.assert_response_success(filters_id)?;
+    let filters_text = filters_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let filters_json: Value = serde_json::from_str(filters_text)?;
+    assert!(filters_json["commands"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|c| c["name"] == "where"));
+
+    let describe_id =
+        harness.send_tool_call("describe_command", json!({ "name": "str replace" }))?;
+    let describe_response = harness.assert_response_success(describe_id)?;
+    let describe_text = describe_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let describe_json: Value = serde_json::from_str(describe_text)?;
+    assert_eq!(describe_json["name"], "str replace");
+    let signature = &describe_json["signatures"][0];
+    assert!(signature["flags"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|f| f["name"] == "all"));
+    assert!(!describe_json["examples"].as_array().unwrap().is_empty());
+
+    let unknown_id =
+        harness.send_tool_call("describe_command", json!({ "name": "no-such-command" }))?;
+    harness.assert_response_error(unknown_id, "Unknown command")?;
+
+    Ok(())
+}
+
+#[test]
+fn test_tools_module_commands_become_tools() -> Result<(), Box<dyn std::error::Error>> {
+    let fixtures_path = std::env::current_dir()?.join("tests/fixtures");
+    let module_path = fixtures_path.join("nushell/tools.nu");
+
+    let mut harness = McpTestHarness::new_with_options(
+        None,
+        Some(vec!["--tools-module", module_path.to_str().unwrap()]),
+    )?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let list_id = harness.send_tools_list()?;
+    let list_response = harness.assert_response_success(list_id)?;
+    let tools = list_response["result"]["tools"].as_array().unwrap();
+    let greet = tools.iter().find(|t| t["name"] == "greet").unwrap();
+    assert_eq!(greet["description"], "Greets someone by name");
+    assert_eq!(greet["inputSchema"]["required"], json!(["name"]));
+    assert_eq!(
+        greet["inputSchema"]["properties"]["shout"]["type"],
+        "boolean"
+    );
+    assert!(tools.iter().any(|t| t["name"] == "sum"));
+
+    let greet_id = harness.send_tool_call("greet", json!({ "name": "nu", "shout": true }))?;
+    let greet_response = harness.assert_response_success(greet_id)?;
+    let greet_text = greet_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let greet_json: Value = serde_json::from_str(greet_text)?;
+    assert_eq!(greet_json["stdout"].as_str().unwrap().trim(), "HELLO, NU!");
+
+    let sum_id = harness.send_tool_call("sum", json!({ "numbers": [1, 2, 3] }))?;
+    let sum_response = harness.assert_response_success(sum_id)?;
+    let sum_text = sum_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let sum_json: Value = serde_json::from_str(sum_text)?;
+    assert_eq!(sum_json["stdout"].as_str().unwrap().trim(), "6");
+
+    let unknown_id = harness.send_tool_call("greet", json!({ "name": "nu", "loud": true }))?;
+    harness.assert_response_error(unknown_id, "Unknown argument")?;
+
+    Ok(())
+}
+
+/// Sends a JSON-RPC message to the streamable HTTP endpoint, with extra
+/// `Name: value` headers, and returns the raw HTTP response.
+fn http_post(
+    port: u16,
+    headers: &[&str],
+    body: &Value,
+) -> Result<String, Box<dyn std::error::Error>> {
+    use std::io::Read;
+
+    let body = serde_json::to_string(body)?;
+    let mut extra_headers: String = headers.iter().map(|h| format!("{h}\r\n")).collect();
+    if !headers
+        .iter()
+        .any(|h| h.to_ascii_lowercase().starts_with("host:"))
+    {
+        extra_headers.push_str("Host: 127.0.0.1\r\n");
+    }
+    let mut stream = std::net::TcpStream::connect(("127.0.0.1", port))?;
+    write!(
+        stream,
+        "POST /mcp HTTP/1.1\r\nContent-Type: application/json\r\nAccept: application/json, text/event-stream\r\n{extra_headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+/// Starts the server with `--transport http` on a free port, plus `args`, and
+/// waits until it accepts connections.
+fn spawn_http_server(args: &[&str]) -> Result<(Child, u16), Box<dyn std::error::Error>> {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    let bind = format!("127.0.0.1:{port}");
+    let server = Command::cargo_bin("mcp-server-nu")?
+        .args(["--transport", "http", "--bind", &bind])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    for _ in 0..50 {
+        if std::net::TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return Ok((server, port));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    Err("server did not start".into())
+}
+
+fn http_initialize_request() -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2025-03-26",
+            "capabilities": {},
+            "clientInfo": { "name": "test-client", "version": "1.0.0" }
+        }
+    })
+}
+
+/// The `Mcp-Session-Id` header of an HTTP response.
+fn http_session_id(response: &str) -> String {
+    response
+        .lines()
+        .find_map(|line| {
+            line.to_ascii_lowercase()
+                .starts_with("mcp-session-id:")
+                .then(|| line.split_once(':').unwrap().1.trim().to_string())
+        })
+        .expect("no Mcp-Session-Id header")
+}
+
+#[test]
+fn test_http_transport_serves_multiple_clients() -> Result<(), Box<dyn std::error::Error>> {
+    let (mut server, port) = spawn_http_server(&[])?;
+
+    let mut session_ids = Vec::new();
+    for _ in 0..2 {
+        let response = http_post(port, &[], &http_initialize_request())?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.contains("\"serverInfo\""));
+        session_ids.push(http_session_id(&response));
+    }
+    assert_ne!(session_ids[0], session_ids[1]);
+
+    for session_id in &session_ids {
+        let session_header = format!("Mcp-Session-Id: {session_id}");
+        http_post(
+            port,
+            &[&session_header],
+            &json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+        )?;
+        let response = http_post(
+            port,
+            &[&session_header],
+            &json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" }),
+        )?;
+        assert!(response.contains("\"exec\""), "{response}");
+    }
+
+    server.kill()?;
+    server.wait()?;
+    Ok(())
+}
+
+#[test]
+fn test_http_bearer_token_auth() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let tokens_path = dir.path().join("tokens");
+    std::fs::write(
+        &tokens_path,
+        "# full access\nadmin-token\nreader-token=list_commands,check\n",
+    )?;
+
+    // Tokens that are configured but empty don't leave the server open
+    let empty_path = dir.path().join("empty");
+    std::fs::write(&empty_path, "# no tokens yet\n")?;
+    let output = Command::cargo_bin("mcp-server-nu")?
+        .args(["--transport", "http", "--bind", "127.0.0.1:0"])
+        .args(["--auth-tokens", empty_path.to_str().unwrap()])
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No auth tokens found"));
+    let output = Command::cargo_bin("mcp-server-nu")?
+        .args(["--transport", "http", "--bind", "127.0.0.1:0"])
+        .env("MCP_SERVER_NU_AUTH_TOKENS", " ")
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No auth tokens found"));
+
+    // Tokens only guard the HTTP transport
+    let output = Command::cargo_bin("mcp-server-nu")?
+        .args(["--auth-tokens", tokens_path.to_str().unwrap()])
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("auth_tokens requires transport to be http"));
+
+    let (mut server, port) = spawn_http_server(&["--auth-tokens", tokens_path.to_str().unwrap()])?;
+
+    // Missing and unknown tokens are rejected
+    let response = http_post(port, &[], &http_initialize_request())?;
+    assert!(response.starts_with("HTTP/1.1 401"), "{response}");
+    assert!(response.contains("-32001"));
+    let response = http_post(
+        port,
+        &["Authorization: Bearer wrong-token"],
+        &http_initialize_request(),
+    )?;
+    assert!(response.starts_with("HTTP/1.1 401"), "{response}");
+
+    // A restricted token only sees and calls its own tools
+    let reader = "Authorization: Bearer reader-token";
+    let response = http_post(port, &[reader], &http_initialize_request())?;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    let session_header = format!("Mcp-Session-Id: {}", http_session_id(&response));
+    let reader_headers = [reader, session_header.as_str()];
+    http_post(
+        port,
+        &reader_headers,
+        &json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+    )?;
+    let response = http_post(
+        port,
+        &reader_headers,
+        &json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" }),
+    )?;
+    assert!(response.contains("\"list_commands\""), "{response}");
+    assert!(!response.contains("\"exec\""), "{response}");
+    let response = http_post(
+        port,
+        &reader_headers,
+        &json!({
+            "jsonrpc": "2.0",
+            "id": 3,
+            "method": "tools/call",
+            "params": { "name": "exec", "arguments": { "script": "ls" } }
+        }),
+    )?;
+    assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+    assert!(response.contains("-32003"));
+
+    // Their requests are buffered to find the tool, but only up to a limit
+    let response = http_post(
+        port,
+        &reader_headers,
+        &json!({
+            "jsonrpc": "2.0",
+            "id": 4,
+            "method": "tools/call",
+            "params": { "name": "check", "arguments": { "script": "1".repeat(17 << 20) } }
+        }),
+    )?;
+    assert!(response.starts_with("HTTP/1.1 413"), "{response}");
+
+    // An unrestricted token sees every tool
+    let admin = "Authorization: Bearer admin-token";
+    let response = http_post(port, &[admin], &http_initialize_request())?;
+    let session_header = format!("Mcp-Session-Id: {}", http_session_id(&response));
+    let admin_headers = [admin, session_header.as_str()];
+    http_post(
+        port,
+        &admin_headers,
+        &json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+    )?;
+    let response = http_post(
+        port,
+        &admin_headers,
+        &json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" }),
+    )?;
+    assert!(response.contains("\"exec\""), "{response}");
+
+    server.kill()?;
+    server.wait()?;
+
+    // Scripts can't read the tokens from the server's environment
+    let mut harness =
+        McpTestHarness::new_with_env(Some(vec![("MCP_SERVER_NU_AUTH_TOKENS", "secret-token")]))?;
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+    let result = harness.call_tool_json(
+        "exec",
+        json!({ "script": "$env.MCP_SERVER_NU_AUTH_TOKENS? | default 'unset'" }),
+    )?;
+    assert_eq!(result["stdout"], "unset\n");
+    Ok(())
+}
+
+#[test]
+fn test_http_rejects_foreign_hosts_and_origins() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::cargo_bin("mcp-server-nu")?
+        .args(["--allowed-origin", "https://app.example.com"])
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("allowed_origins requires transport to be http"));
+
+    let (mut server, port) = spawn_http_server(&["--allowed-origin", "https://app.example.com"])?;
+
+    // DNS rebinding: a page on attacker.example resolving to 127.0.0.1
+    let response = http_post(
+        port,
+        &["Host: attacker.example:8000"],
+        &http_initialize_request(),
+    )?;
+    assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+    assert!(response.contains("-32003"));
+
+    let response = http_post(
+        port,
+        &["Origin: http://attacker.example"],
+        &http_initialize_request(),
+    )?;
+    assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+    let response = http_post(port, &["Origin: null"], &http_initialize_request())?;
+    assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+
+    for origin in ["http://localhost:3000", "https://app.example.com"] {
+        let header = format!("Origin: {origin}");
+        let response = http_post(port, &[&header], &http_initialize_request())?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{origin}: {response}");
+    }
+    let response = http_post(port, &["Host: localhost:8000"], &http_initialize_request())?;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+    server.kill()?;
+    server.wait()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_socket_serves_each_connection() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let dir = tempfile::tempdir()?;
+    let socket_path = dir.path().join("mcp.sock");
+    // A socket left behind by a server that's no longer running
+    drop(UnixListener::bind(&socket_path)?);
+
+    let mut server = Command::cargo_bin("mcp-server-nu")?
+        .args(["--listen-unix", socket_path.to_str().unwrap()])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let connect = || -> Result<UnixStream, Box<dyn std::error::Error>> {
+        for _ in 0..50 {
+            if let Ok(stream) = UnixStream::connect(&socket_path) {
+                return Ok(stream);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        Err("server did not start".into())
+    };
+
+    let mut clients = [connect()?, connect()?];
+    let mode = std::fs::metadata(&socket_path)?.permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    // The private directory the socket was created in is gone.
+    let entries = std::fs::read_dir(socket_path.parent().unwrap())?.count();
+    assert_eq!(entries, 1);
+
+    for (i, client) in clients.iter_mut().enumerate() {
+        let initialize = json!({
+            "jsonrpc": "2.0",
+            "id": i,
+            "method": "initialize",
+            "params": {
+                "protocolVersion": "2024-11-05",
+                "capabilities": {},
+                "clientInfo": { "name": "test-client", "version": "1.0.0" }
+            }
+        });
+        writeln!(client, "{}", serde_json::to_string(&initialize)?)?;
+    }
+    for (i, client) in clients.iter().enumerate() {
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line)?;
+        let response: Value = serde_json::from_str(&line)?;
+        assert_eq!(response["id"], i);
+        assert!(response["result"]["serverInfo"].is_object());
+    }
+
+    // The socket is removed on shutdown
+    unsafe { libc::kill(server.id() as i32, libc::SIGTERM) };
+    server.wait()?;
+    assert!(!socket_path.exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_policy_rejects_denied_commands() -> Result<(), Box<dyn std::error::Error>> {
+    let fixtures_path = std::env::current_dir()?.join("tests/fixtures");
+    let policy_path = fixtures_path.join("policy.toml");
+
+    let mut harness = McpTestHarness::new_with_options(
+        None,
+        Some(vec!["--policy", policy_path.to_str().unwrap()]),
+    )?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let denied_id = harness.send_tool_call(
+        "exec",
+        json!({ "script": "ls | length\nrm -rf some-dir; ^curl example.com" }),
+    )?;
+    let response = harness.assert_response_error(denied_id, "rm, ^curl")?;
+    let violations = response["error"]["data"]["violations"].as_array().unwrap();
+    assert_eq!(violations[0]["command"], "rm");
+    assert_eq!(violations[0]["reason"], "denied");
+    assert_eq!(violations[0]["start"], json!({ "line": 2, "column": 1 }));
+    assert_eq!(violations[1]["command"], "curl");
+    assert_eq!(violations[1]["external"], true);
+
+    // Background jobs are checked too
+    let job_id = harness.send_tool_call(
+        "job_start",
+        json!({ "script": "http post https://example.com body" }),
+    )?;
+    harness.assert_response_error(job_id, "not permitted by policy")?;
+
+    // Externals run through run-external are checked by name, and ones
+    // chosen at runtime are rejected
+    let run_external_id =
+        harness.send_tool_call("exec", json!({ "script": "run-external curl example.com" }))?;
+    let response = harness.assert_response_error(run_external_id, "^curl")?;
+    assert_eq!(
+        response["error"]["data"]["violations"][0]["reason"],
+        "denied"
+    );
+    for script in [
+        "let c = 'curl'; ^$c example.com",
+        "let c = 'curl'; run-external $c example.com",
+    ] {
+        let id = harness.send_tool_call("exec", json!({ "script": script }))?;
+        let response = harness.assert_response_error(id, "not permitted by policy")?;
+        assert_eq!(
+            response["error"]["data"]["violations"][0]["reason"],
+            "unverifiable"
+        );
+    }
+
+    // Code loaded from files isn't visible to the policy, so it's rejected;
+    // the standard library is fine
+    let dir = tempfile::tempdir()?;
+    let module = dir.path().join("helper.nu");
+    std::fs::write(&module, "export def helper [] { rm -rf some-dir }\n")?;
+    let module = module.to_str().unwrap();
+    for script in [
+        format!("source {module}"),
+        format!("use {module} helper; helper"),
+        format!("overlay use {module}"),
+    ] {
+        let id = harness.send_tool_call("exec", json!({ "script": script }))?;
+        let response = harness.assert_response_error(id, "not permitted by policy")?;
+        assert_eq!(
+            response["error"]["data"]["violations"][0]["reason"],
+            "unverifiable"
+        );
+    }
+    let result = harness.call_tool_json(
+        "exec",
+        json!({ "script": "use std assert; assert equal 1 1; 'ok'" }),
+    )?;
+    assert_eq!(result["stdout"], "ok\n");
+
+    // Denied names in strings aren't commands
+    let allowed_id =
+        harness.send_tool_call("exec", json!({ "script": "'rm -rf' | str length" }))?;
+    let allowed_response = harness.assert_response_success(allowed_id)?;
+    let allowed_text = allowed_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let allowed_json: Value = serde_json::from_str(allowed_text)?;
+    assert_eq!(allowed_json["stdout"].as_str().unwrap().trim(), "6");
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_read_only_mode_blocks_writes() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let target = dir.path().join("written");
+    let target_str = target.to_str().unwrap();
+
+    let mut harness = McpTestHarness::new_with_options(
+        None,
+        Some(vec!["--read-only", "--allow-external", "sh"]),
+    )?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    // Mutating builtins and unlisted externals are rejected before running
+    let save_id = harness.send_tool_call(
+        "exec",
+        json!({ "script": format!("'data' | save {target_str}") }),
+    )?;
+    harness.assert_response_error(save_id, "save")?;
+    let touch_id =
+        harness.send_tool_call("exec", json!({ "script": format!("^touch {target_str}") }))?;
+    harness.assert_response_error(touch_id, "^touch")?;
+
+    // Allowed externals still can't write
+    let sh_id = harness.send_tool_call(
+        "exec",
+        json!({ "script": format!("^sh -c 'echo data > {target_str}'") }),
+    )?;
+    let sh_response = harness.assert_response_success(sh_id)?;
+    let sh_text = sh_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let sh_json: Value = serde_json::from_str(sh_text)?;
+    assert_ne!(sh_json["exit_code"], 0);
+    assert!(!target.exists());
+
+    // Reading, sessions and structured output still work
+    let open_id = harness.send_tool_call("session_open", json!({}))?;
+    let open_response = harness.assert_response_success(open_id)?;
+    let open_text = open_response["result"]["content"][0]["text"]
+        .as_str()
+        .unwrap();
+    let session_id = serde_json::from_str::<Value>(open_text)?["session_id"].clone();
+    let ls_id = harness.send_tool_call(
+        "exec",
+        json!({
+            "script": "ls / | length",
+            "session_id": session_id,
+            "output_format": "json"
+        }),
+    )?;
+    let ls_response = harness.assert_response_success(ls_id)?;
+    assert!(ls_response["result"]["structuredContent"]["value"].as_u64() > Some(0));
+
+    // Commands defined by earlier calls aren't mistaken for externals
+    harness.call_tool_json(
+        "exec",
+        json!({
+            "script": "def greet [] { 'hello' }",
+            "session_id": session_id
+        }),
+    )?;
+    let greet = harness.call_tool_json(
+        "exec",
+        json!({ "script": "greet", "session_id": session_id }),
+    )?;
+    assert_eq!(greet["stdout"], "hello\n");
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_sandbox_restricts_writes_and_network() -> Result<(), Box<dyn std::error::Error>> {
+    let writable = tempfile::tempdir()?;
+    let outside = tempfile::tempdir()?;
+    let allowed = writable.path().join("allowed");
+    let denied = outside.path().join("denied");
+
+    let mut harness = McpTestHarness::new_with_options(
+        None,
+        Some(vec![
+            "--sandbox",
+            "--sandbox-write",
+            writable.path().to_str().unwrap(),
+            "--sandbox-no-network",
+        ]),
+    )?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let result = harness.call_tool_json(
+        "exec",
+        json!({ "script": format!("'data' | save {}", allowed.to_str().unwrap()) }),
+    )?;
+    assert_eq!(result["exit_code"], 0);
+    assert!(allowed.exists());
+
+    let result = harness.call_tool_json(
+        "exec",
+        json!({ "script": format!("'data' | save {}", denied.to_str().unwrap()) }),
+    )?;
+    assert_ne!(result["exit_code"], 0);
+    assert!(!denied.exists());
+    assert_eq!(result["sandbox"]["restrictions"]["network"], false);
+
+    let result =
+        harness.call_tool_json("exec", json!({ "script": "http get http://127.0.0.1:9/" }))?;
+    assert_ne!(result["exit_code"], 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_resource_limits_report_exceeded_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let big = dir.path().join("big");
+
+    let mut harness =
+        McpTestHarness::new_with_options(None, Some(vec!["--limit-file-size-mb", "1"]))?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let small_id = harness.send_tool_call("exec", json!({ "script": "1 + 1" }))?;
+    let response = harness.assert_response_success(small_id)?;
+    let text = response["result"]["content"][0]["text"].as_str().unwrap();
+    let result: Value = serde_json::from_str(text)?;
+    assert_eq!(result["exit_code"], 0);
+    assert!(result.get("limit_exceeded").is_none());
+
+    let script = format!(
+        "0..2097152 | each {{ 'x' }} | str join | save {}",
+        big.to_str().unwrap()
+    );
+    let big_id = harness.send_tool_call("exec", json!({ "script": script }))?;
+    let response = harness.assert_response_success(big_id)?;
+    let text = response["result"]["content"][0]["text"].as_str().unwrap();
+    let result: Value = serde_json::from_str(text)?;
+    assert_ne!(result["exit_code"], 0);
+    assert_eq!(result["limit_exceeded"]["limit"], "file_size_mb");
+    assert_eq!(result["limit_exceeded"]["value"], 1);
+    assert_eq!(result["limit_exceeded"]["certain"], true);
+
+    Ok(())
+}
+
+#[test]
+fn test_oversized_output_is_truncated_and_paged() -> Result<(), Box<dyn std::error::Error>> {
+    let mut harness =
+        McpTestHarness::new_with_options(None, Some(vec!["--max-output-bytes", "200"]))?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let result = harness.call_tool_json(
+        "exec",
+        json!({ "script": "1..2000 | each { into string } | str join (char nl) | print" }),
+    )?;
+    let stdout = result["stdout"].as_str().unwrap();
+    assert!(stdout.starts_with("1\n2\n"), "stdout: {stdout}");
+    assert!(stdout.contains("bytes truncated"));
+    assert!(stdout.trim_end().ends_with("2000"));
+    let total_bytes = result["truncated"]["stdout"]["total_bytes"]
+        .as_u64()
+        .unwrap();
+    let output_id = result["truncated"]["output_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let mut full = String::new();
+    let mut offset = json!(0);
+    while !offset.is_null() {
+        let page = harness.call_tool_json(
+            "read_output",
+            json!({ "output_id": output_id, "offset": offset, "length": 4096 }),
+        )?;
+        full.push_str(page["text"].as_str().unwrap());
+        offset = page["next_offset"].clone();
+    }
+    assert_eq!(full.len() as u64, total_bytes);
+    assert!(full.contains("\n1000\n"));
+
+    let unknown_id = harness.send_tool_call("read_output", json!({ "output_id": "o999" }))?;
+    harness.assert_response_error(unknown_id, "Unknown output")?;
+
+    // A structured value too large to return is stored for read_output
+    let result = harness.call_tool_json(
+        "exec",
+        json!({ "script": "1..100 | each {|n| { n: $n } }", "output_format": "json" }),
+    )?;
+    assert!(result["value"].is_null());
+    assert_eq!(result["truncated"]["value"]["stored"], true);
+    let page = harness.call_tool_json(
+        "read_output",
+        json!({
+            "output_id": result["truncated"]["output_id"],
+            "stream": "value",
+            "length": 1_000_000,
+        }),
+    )?;
+    let value: Value = serde_json::from_str(page["text"].as_str().unwrap())?;
+    assert_eq!(value.as_array().unwrap().len(), 100);
+    assert_eq!(value[99]["n"], 100);
+
+    Ok(())
+}
+
+#[test]
+fn test_exec_cwd_and_env() -> Result<(), Box<dyn std::error::Error>> {
+    let root = tempfile::tempdir()?;
+    let project = root.path().join("project");
+    std::fs::create_dir(&project)?;
+    let project = project.canonicalize()?;
+    let outside = tempfile::tempdir()?;
+
+    let mut harness = McpTestHarness::new_with_options(
+        Some(vec![("MCP_TEST_INHERITED", "yes")]),
+        Some(vec!["--allowed-root", root.path().to_str().unwrap()]),
+    )?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let result = harness.call_tool_json(
+        "exec",
+        json!({
+            "script": "$'($env.PWD)|($env.GREETING)|($env.MCP_TEST_INHERITED)'",
+            "cwd": project.to_str().unwrap(),
+            "env": { "GREETING": "hello" },
+        }),
+    )?;
+    assert_eq!(result["exit_code"], 0);
+    assert_eq!(
+        result["stdout"].as_str().unwrap().trim(),
+        format!("{}|hello|yes", project.display())
+    );
+    assert_eq!(result["cwd"], project.to_str().unwrap());
+
+    let result = harness.call_tool_json(
+        "exec",
+        json!({
+            "script": "$env.MCP_TEST_INHERITED? | default 'unset'",
+            "inherit_env": false,
+        }),
+    )?;
+    assert_eq!(result["stdout"].as_str().unwrap().trim(), "unset");
+
+    let outside_id = harness.send_tool_call(
+        "exec",
+        json!({ "script": "pwd", "cwd": outside.path().to_str().unwrap() }),
+    )?;
+    harness.assert_response_error(outside_id, "outside the allowed roots")?;
+
+    Ok(())
+}
+
+#[test]
+fn test_exec_input_is_passed_as_in() -> Result<(), Box<dyn std::error::Error>> {
+    let mut harness = McpTestHarness::new()?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let result = harness.call_tool_json(
+        "exec",
+        json!({
+            "script": "$in | where age > 30 | get name",
+            "input": r#"[{"name": "ann", "age": 41}, {"name": "bob", "age": 29}]"#,
+            "input_format": "json",
+            "output_format": "json",
+        }),
+    )?;
+    assert_eq!(result["value"], json!(["ann"]));
+
+    let result = harness.call_tool_json(
+        "exec",
+        json!({
+            "script": "$in | get qty | math sum",
+            "input": "item,qty\napple,3\npear,4\n",
+            "input_format": "csv",
+            "output_format": "json",
+        }),
+    )?;
+    assert_eq!(result["value"], 7);
+
+    let result = harness.call_tool_json(
+        "exec",
+        json!({ "script": "$in | str length", "input": "it's \"quoted\"" }),
+    )?;
+    assert_eq!(result["stdout"].as_str().unwrap().trim(), "13");
+
+    let invalid_id = harness.send_tool_call(
+        "exec",
+        json!({ "script": "$in", "input": "{", "input_format": "json" }),
+    )?;
+    harness.assert_response_error(invalid_id, "Input is not valid JSON")?;
+
+    Ok(())
+}
+
+#[test]
+fn test_exec_args_become_variables() -> Result<(), Box<dyn std::error::Error>> {
+    let mut harness = McpTestHarness::new()?;
+
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let tricky = "it's \"$(rm -rf /)\" ; ) '#";
+    let id = harness.send_tool_call(
+        "exec",
+        json!({
+            "script": "{ name: $name, doubled: ($count * 2), tags: ($tags | length) }",
+            "args": { "name": tricky, "count": 21, "tags": ["a", "b"] },
+            "output_format": "json",
+        }),
+    )?;
+    let response = harness.assert_response_success(id)?;
+    let value = &response["result"]["structuredContent"]["value"];
+    assert_eq!(value["name"], tricky);
+    assert_eq!(value["doubled"], 42);
+    assert_eq!(value["tags"], 2);
+
+    let invalid_id = harness.send_tool_call(
+        "exec",
+        json!({ "script": "1", "args": { "not a name": 1 } }),
+    )?;
+    harness.assert_response_error(invalid_id, "Invalid argument name")?;
+
+    Ok(())
+}
+
+#[test]
+fn test_config_file_profiles() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let config = dir.path().join("config.toml");
+    std::fs::write(
+        &config,
+        r#"
+max_timeout_seconds = 60
+env = { MCP_TEST_GREETING = "from config" }
+
+[profiles.strict]
+max_timeout_seconds = 5
+"#,
+    )?;
+    let config = config.to_str().unwrap();
+
+    let output = Command::cargo_bin("mcp-server-nu")?
+        .args(["--config", config, "--profile", "missing"])
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Unknown profile 'missing'"),
+        "stderr: {stderr}"
+    );
+    assert!(stderr.contains("strict"), "stderr: {stderr}");
+
+    let output = Command::cargo_bin("mcp-server-nu")?
+        .args(["--config", config, "--sandbox-write", "/tmp"])
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("sandbox_write requires sandbox to be enabled"));
+
+    let mut harness = McpTestHarness::new_with_options(
+        None,
+        Some(vec!["--config", config, "--profile", "strict"]),
+    )?;
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let too_long_id =
+        harness.send_tool_call("exec", json!({ "script": "1", "timeout_seconds": 30 }))?;
+    harness.assert_response_error(too_long_id, "exceeds the server's maximum of 5")?;
+
+    let env_id = harness.send_tool_call("exec", json!({ "script": "$env.MCP_TEST_GREETING" }))?;
+    let response = harness.assert_response_success(env_id)?;
+    let text = response["result"]["content"][0]["text"].as_str().unwrap();
+    let result: Value = serde_json::from_str(text)?;
+    assert_eq!(result["stdout"].as_str().unwrap().trim(), "from config");
+
+    Ok(())
+}
+
+#[test]
+fn test_watch_reloads_config_file() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let config = dir.path().join("config.toml");
+    std::fs::write(&config, "max_timeout_seconds = 60\n")?;
+
+    let mut harness = McpTestHarness::new_with_options(
+        None,
+        Some(vec!["--config", config.to_str().unwrap(), "--watch"]),
+    )?;
+    let init_id = harness.send_initialize()?;
+    let response = harness.assert_response_success(init_id)?;
+    assert_eq!(
+        response["result"]["capabilities"]["tools"]["listChanged"],
+        true
+    );
+    harness.send_initialized_notification()?;
+
+    let before_id =
+        harness.send_tool_call("exec", json!({ "script": "1", "timeout_seconds": 100 }))?;
+    harness.assert_response_error(before_id, "exceeds the server's maximum of 60")?;
+
+    // An invalid config is ignored and the current one kept.
+    std::fs::write(&config, "max_timeout_seconds = 0\n")?;
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    let invalid_id =
+        harness.send_tool_call("exec", json!({ "script": "1", "timeout_seconds": 100 }))?;
+    harness.assert_response_error(invalid_id, "exceeds the server's maximum of 60")?;
+
+    std::fs::write(&config, "max_timeout_seconds = 5\n")?;
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    loop {
+        let id =
+            harness.send_tool_call("exec", json!({ "script": "1", "timeout_seconds": 100 }))?;
+        let response = harness.read_response()?;
+        assert_eq!(response["id"], id);
+        let message = response["error"]["message"].as_str().unwrap_or("");
+        if message.contains("exceeds the server's maximum of 5") {
+            break;
+        }
+        assert!(
+            std::time::Instant::now() < deadline,
+            "config was not reloaded: {message}"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(200));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_nu_binary_version_probe() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::cargo_bin("mcp-server-nu")?
+        .args(["--nu-bin", "/nonexistent/nu"])
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("nu binary '/nonexistent/nu' not found"),
+        "stderr: {stderr}"
+    );
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        let old_nu = dir.path().join("nu");
+        std::fs::write(&old_nu, "#!/bin/sh\necho 0.1.0\n")?;
+        std::fs::set_permissions(&old_nu, std::fs::Permissions::from_mode(0o755))?;
+        let output = Command::cargo_bin("mcp-server-nu")?
+            .args(["--nu-bin", old_nu.to_str().unwrap()])
+            .stdin(Stdio::null())
+            .output()?;
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("nu 0.1.0") && stderr.contains("is not supported"),
+            "stderr: {stderr}"
+        );
+    }
+
+    let mut harness = McpTestHarness::new()?;
+    let init_id = harness.send_initialize()?;
+    let response = harness.assert_response_success(init_id)?;
+    let instructions = response["result"]["instructions"].as_str().unwrap();
+    harness.send_initialized_notification()?;
+
+    let status_id = harness.send_tool_call("server_status", json!({}))?;
+    let response = harness.assert_response_success(status_id)?;
+    let text = response["result"]["content"][0]["text"].as_str().unwrap();
+    let status: Value = serde_json::from_str(text)?;
+    assert_eq!(status["nu"]["bin"], "nu");
+    let version = status["nu"]["version"].as_str().unwrap();
+    assert!(
+        instructions.contains(&format!("Nushell {version}")),
+        "instructions: {instructions}"
+    );
+    assert_eq!(status["server_version"], env!("CARGO_PKG_VERSION"));
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_log_records_exec() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::cargo_bin("mcp-server-nu")?
+        .args(["--audit-scripts", "hash"])
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("audit_scripts requires audit_log to be set"));
+
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("audit.jsonl");
+    let mut harness =
+        McpTestHarness::new_with_options(None, Some(vec!["--audit-log", log.to_str().unwrap()]))?;
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+
+    let exec_id = harness.send_tool_call(
+        "exec",
+        json!({
+            "script": "$env.MCP_TEST_AUDIT",
+            "env": { "MCP_TEST_AUDIT": "audited" },
+            "args": { "name": "audited" },
+            "input": "piped",
+        }),
+    )?;
+    harness.assert_response_success(exec_id)?;
+    let invalid_id = harness.send_tool_call(
+        "exec",
+        json!({ "script": "1", "cwd": "/nonexistent/mcp-test-audit" }),
+    )?;
+    harness.assert_response_error(invalid_id, "Invalid cwd")?;
+
+    let entries = read_audit_log(&log, 2)?;
+
+    let entry = &entries[0];
+    assert_eq!(entry["tool"], "exec");
+    assert_eq!(entry["client"]["name"], "test-client");
+    assert_eq!(entry["script"], "$env.MCP_TEST_AUDIT");
+    assert_eq!(entry["env"]["MCP_TEST_AUDIT"], "audited");
+    assert_eq!(entry["args"]["name"], "audited");
+    assert_eq!(entry["input"], "piped");
+    assert_eq!(entry["policy"], "unchecked");
+    assert_eq!(entry["exit_code"], 0);
+    assert_eq!(entry["stdout_bytes"], "audited\n".len());
+    assert!(entry["duration_ms"].is_u64());
+    assert!(entry["timestamp"].is_string());
+
+    let entry = &entries[1];
+    assert!(entry["exit_code"].is_null());
+    assert!(entry["error"].as_str().unwrap().contains("Invalid cwd"));
+
+    // Background jobs get a second entry once they finish
+    let job = harness.call_tool_json("job_start", json!({ "script": "print done" }))?;
+    harness.call_tool_json("job_wait", json!({ "job_id": job["job_id"] }))?;
+    let entries = read_audit_log(&log, 4)?;
+    assert_eq!(entries[2]["event"], "started");
+    assert_eq!(entries[2]["job_id"], job["job_id"]);
+    assert!(entries[2]["exit_code"].is_null());
+    assert_eq!(entries[3]["event"], "finished");
+    assert_eq!(entries[3]["job_id"], job["job_id"]);
+    assert_eq!(entries[3]["exit_code"], 0);
+    assert_eq!(entries[3]["stdout_bytes"], "done\n".len());
+
+    // Redacting scripts also redacts environment values, arguments and input
+    let log = dir.path().join("redacted.jsonl");
+    let mut harness = McpTestHarness::new_with_options(
+        None,
+        Some(vec![
+            "--audit-log",
+            log.to_str().unwrap(),
+            "--audit-scripts",
+            "redact",
+        ]),
+    )?;
+    let init_id = harness.send_initialize()?;
+    harness.assert_response_success(init_id)?;
+    harness.send_initialized_notification()?;
+    harness.call_tool_json(
+        "exec",
+        json!({
+            "script": "$env.MCP_TEST_AUDIT",
+            "env": { "MCP_TEST_AUDIT": "secret" },
+            "args": { "token": "secret" },
+            "input": "secret",
+        }),
+    )?;
+    let entries = read_audit_log(&log, 1)?;
+    assert!(entries[0].get("script").is_none());
+    assert_eq!(entries[0]["script_bytes"], "$env.MCP_TEST_AUDIT".len());
+    assert_eq!(entries[0]["env"]["MCP_TEST_AUDIT"], "[redacted]");
+    assert_eq!(entries[0]["args"]["token"], "[redacted]");
+    assert_eq!(entries[0]["input"], "[redacted]");
+
+    // A policy check that can't run isn't logged as a denial
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let broken_nu = dir.path().join("nu");
+        std::fs::write(
+            &broken_nu,
+            "#!/bin/sh\n[ \"$1\" = --version ] && echo 0.99.0 || exit 1\n",
+        )?;
+        std::fs::set_permissions(&broken_nu, std::fs::Permissions::from_mode(0o755))?;
+        let policy = dir.path().join("policy.toml");
+        std::fs::write(&policy, "deny = [\"rm\"]\n")?;
+        let log = dir.path().join("broken.jsonl");
+        let mut harness = McpTestHarness::new_with_options(
+            None,
+            Some(vec![
+                "--nu-bin",
+                broken_nu.to_str().unwrap(),
+                "--policy",
+                policy.to_str().unwrap(),
+                "--audit-log",
+                log.to_str().unwrap(),
+            ]),
+        )?;
+        let init_id = harness.send_initialize()?;
+        harness.assert_response_success(init_id)?;
+        harness.send_initialized_notification()?;
+        let exec_id = harness.send_tool_call("exec", json!({ "script": "ls" }))?;
+        harness.assert_response_error(exec_id, "Policy check failed")?;
+        let entries = read_audit_log(&log, 1)?;
+        assert_eq!(entries[0]["policy"], "error");
+        assert!(entries[0]["violations"].is_null());
+        assert!(entries[0]["error"]
+            .as_str()
+            .unwrap()
+            .contains("Policy check failed"));
+    }
+
+    Ok(())
+}
+
+/// Reads the audit log at `path` once it holds `count` entries.
+fn read_audit_log(
+    path: &std::path::Path,
+    count: usize,
+) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    for _ in 0..50 {
+        let text = std::fs::read_to_string(path)?;
+        if text.lines().count() >= count {
+            let entries: Vec<Value> = text
+                .lines()
+                .map(serde_json::from_str)
+                .collect::<Result<_, _>>()?;
+            assert_eq!(entries.len(), count, "log: {text}");
+            return Ok(entries);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    Err(format!("audit log {} never got {count} entries", path.display()).into())
+}